@@ -1,9 +1,13 @@
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::utils;
 
 /// all configs of rime-ls
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     /// if enabled
     #[serde(default = "default_enabled")]
@@ -17,6 +21,9 @@ pub struct Config {
     /// rime log data dir
     #[serde(default = "default_log_dir")]
     pub log_dir: PathBuf,
+    /// minimal level of rime log
+    #[serde(default = "default_log_level")]
+    pub log_level: LogLevel,
     /// max number of candidates
     #[serde(default = "default_max_candidates")]
     pub max_candidates: usize,
@@ -35,7 +42,7 @@ pub struct Config {
     /// if CompletionItem is always incomplete
     #[serde(default = "default_always_incomplete")]
     pub always_incomplete: bool,
-    /// if preselect first CompletionItem
+    /// if preselect the highlighted (usually the first) CompletionItem
     #[serde(default = "default_preselect_first")]
     pub preselect_first: bool,
     /// if including word prefix in filter_text
@@ -47,11 +54,96 @@ pub struct Config {
     /// if showing order in label
     #[serde(default = "default_show_order_in_label")]
     pub show_order_in_label: bool,
+    /// where candidates come from, current menu page or all pages
+    #[serde(default = "default_candidate_source")]
+    pub candidate_source: CandidateSource,
+    /// schema selected when creating session for a document, keyed by languageId or uri glob
+    #[serde(default = "default_default_schemas")]
+    pub default_schemas: HashMap<String, String>,
+    /// rime options set when creating session, e.g. `{ "full_shape": false }`
+    #[serde(default = "default_default_options")]
+    pub default_options: HashMap<String, bool>,
+    /// if deploying automatically when yaml files in user data dir change
+    #[serde(default = "default_auto_deploy")]
+    pub auto_deploy: bool,
+    /// where completion works in documents, keyed by languageId, `*` for all languages
+    #[serde(default = "default_completion_scope")]
+    pub completion_scope: HashMap<String, CompletionScope>,
+    /// parts of markdown or LaTeX documents where completion is suppressed, keyed by languageId
+    #[serde(default = "default_markup_skip")]
+    pub markup_skip: HashMap<String, MarkupSkip>,
+    /// settings overridden in workspace folders, keyed by folder name or path
+    #[serde(default = "default_workspace_settings")]
+    pub workspace_settings: HashMap<String, Settings>,
+    /// dictionary for readings in hover, dictionary of current schema if empty
+    #[serde(default = "default_reading_dictionary")]
+    pub reading_dictionary: String,
+}
+
+/// source of candidates in a completion list
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CandidateSource {
+    /// only candidates in current menu page
+    #[default]
+    Page,
+    /// candidates of all pages, at most `max_candidates`
+    All,
+}
+
+/// where completion works in a document
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompletionScope {
+    /// everywhere
+    #[default]
+    All,
+    /// only in comments and strings, everywhere if the language is unknown
+    CommentString,
+}
+
+/// parts of a markup document where completion is suppressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MarkupSkip {
+    /// code blocks, inline code and verbatim
+    pub code: bool,
+    /// inline and display math
+    pub math: bool,
+    /// front matter of markdown
+    pub front_matter: bool,
+}
+
+impl Default for MarkupSkip {
+    fn default() -> Self {
+        MarkupSkip {
+            code: true,
+            math: true,
+            front_matter: true,
+        }
+    }
+}
+
+/// level of rime log, same as glog
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Info = 0,
+    Warning = 1,
+    #[default]
+    Error = 2,
+    Fatal = 3,
 }
 
 /// settings that can be tweaked during running
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Settings {
+    /// rime share data dir, rime is re-initialized when changed
+    pub shared_data_dir: Option<PathBuf>,
+    /// rime user data dir, rime is re-initialized when changed
+    pub user_data_dir: Option<PathBuf>,
+    /// rime log data dir, takes effect after restart
+    pub log_dir: Option<PathBuf>,
     /// enabled
     pub enabled: Option<bool>,
     /// max number of candidates
@@ -66,7 +158,7 @@ pub struct Settings {
     pub max_tokens: Option<usize>,
     /// if CompletionItem is always incomplete
     pub always_incomplete: Option<bool>,
-    /// if preselect first CompletionItem
+    /// if preselect the highlighted (usually the first) CompletionItem
     pub preselect_first: Option<bool>,
     /// if including word prefix in filter_text
     pub long_filter_text: Option<bool>,
@@ -74,6 +166,22 @@ pub struct Settings {
     pub show_filter_text_in_label: Option<bool>,
     /// if showing order in label
     pub show_order_in_label: Option<bool>,
+    /// where candidates come from, current menu page or all pages
+    pub candidate_source: Option<CandidateSource>,
+    /// schema selected when creating session for a document, keyed by languageId or uri glob
+    pub default_schemas: Option<HashMap<String, String>>,
+    /// rime options set when creating session, e.g. `{ "full_shape": false }`
+    pub default_options: Option<HashMap<String, bool>>,
+    /// if deploying automatically when yaml files in user data dir change
+    pub auto_deploy: Option<bool>,
+    /// where completion works in documents, keyed by languageId, `*` for all languages
+    pub completion_scope: Option<HashMap<String, CompletionScope>>,
+    /// parts of markdown or LaTeX documents where completion is suppressed, keyed by languageId
+    pub markup_skip: Option<HashMap<String, MarkupSkip>>,
+    /// settings overridden in workspace folders, keyed by folder name or path
+    pub workspace_settings: Option<HashMap<String, Settings>>,
+    /// dictionary for readings in hover, dictionary of current schema if empty
+    pub reading_dictionary: Option<String>,
 }
 
 macro_rules! apply_setting {
@@ -98,6 +206,7 @@ impl Default for Config {
             shared_data_dir: default_shared_data_dir(),
             user_data_dir: default_user_data_dir(),
             log_dir: default_log_dir(),
+            log_level: default_log_level(),
             max_candidates: default_max_candidates(),
             paging_characters: default_paging_characters(),
             trigger_characters: default_trigger_characters(),
@@ -108,6 +217,14 @@ impl Default for Config {
             long_filter_text: default_long_filter_text(),
             show_filter_text_in_label: default_show_filter_text_in_label(),
             show_order_in_label: default_show_order_in_label(),
+            candidate_source: default_candidate_source(),
+            default_schemas: default_default_schemas(),
+            default_options: default_default_options(),
+            auto_deploy: default_auto_deploy(),
+            completion_scope: default_completion_scope(),
+            markup_skip: default_markup_skip(),
+            workspace_settings: default_workspace_settings(),
+            reading_dictionary: default_reading_dictionary(),
         }
     }
 }
@@ -150,6 +267,10 @@ fn default_log_dir() -> PathBuf {
     proj_dirs.cache_dir().to_path_buf()
 }
 
+fn default_log_level() -> LogLevel {
+    LogLevel::default()
+}
+
 fn default_schema_trigger_character() -> String {
     String::default()
 }
@@ -170,6 +291,161 @@ fn default_show_order_in_label() -> bool {
     true
 }
 
+fn default_candidate_source() -> CandidateSource {
+    CandidateSource::default()
+}
+
+fn default_default_schemas() -> HashMap<String, String> {
+    HashMap::default()
+}
+
+fn default_default_options() -> HashMap<String, bool> {
+    HashMap::default()
+}
+
+fn default_auto_deploy() -> bool {
+    true
+}
+
+fn default_completion_scope() -> HashMap<String, CompletionScope> {
+    HashMap::default()
+}
+
+fn default_markup_skip() -> HashMap<String, MarkupSkip> {
+    ["markdown", "latex", "tex"]
+        .map(|id| (id.to_string(), MarkupSkip::default()))
+        .into()
+}
+
+fn default_workspace_settings() -> HashMap<String, Settings> {
+    HashMap::default()
+}
+
+fn default_reading_dictionary() -> String {
+    String::default()
+}
+
+/// name of config file in user config dir, `rime-ls.toml` or `rime-ls.json`
+const CONFIG_FILE_NAME: &str = "rime-ls";
+
+fn default_config_file() -> Option<PathBuf> {
+    let proj_dirs = ProjectDirs::from("com", "rimels", "Rime-Ls")?;
+    ["toml", "json"]
+        .iter()
+        .map(|ext| {
+            proj_dirs
+                .config_dir()
+                .join(format!("{CONFIG_FILE_NAME}.{ext}"))
+        })
+        .find(|path| path.exists())
+}
+
+/// read config file as the base layer of config, same format as initializationOptions,
+/// `path` defaults to the one in user config dir and it is ok if that one does not exist
+pub fn read_config_file(path: Option<&Path>) -> Result<Map<String, Value>, String> {
+    let path = match path.map(Path::to_path_buf).or_else(default_config_file) {
+        Some(path) => path,
+        None => return Ok(Map::new()),
+    };
+    let display = path.display();
+    let content =
+        std::fs::read_to_string(&path).map_err(|e| format!("failed to read {display}: {e}"))?;
+    let value: Value = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&content).map_err(|e| e.to_string()),
+        _ => serde_json::from_str(&content).map_err(|e| e.to_string()),
+    }
+    .map_err(|e| format!("failed to parse {display}: {e}"))?;
+    match value {
+        Value::Object(map) => Ok(map),
+        _ => Err(format!("{display} is not a table of config")),
+    }
+}
+
+impl Config {
+    /// build config field by field, so that an invalid field falls back to its default value
+    /// without affecting others, returns warnings of invalid and unknown fields
+    pub fn from_object(map: Map<String, Value>) -> (Config, Vec<String>) {
+        let known = match serde_json::to_value(Config::default()) {
+            Ok(Value::Object(known)) => known,
+            _ => Map::new(),
+        };
+        let mut valid = Map::new();
+        let mut warnings = Vec::new();
+        for (key, value) in map {
+            if !known.contains_key(&key) {
+                warnings.push(format!("unknown config \"{key}\" is ignored"));
+                continue;
+            }
+            // other fields use default values, so only this one is checked
+            let field = Map::from_iter([(key.clone(), value)]);
+            match serde_json::from_value::<Config>(Value::Object(field.clone())) {
+                Ok(_) => valid.extend(field),
+                Err(e) => warnings.push(format!("invalid config \"{key}\" is ignored: {e}")),
+            }
+        }
+        let config = serde_json::from_value(Value::Object(valid)).unwrap_or_default();
+        (config, warnings)
+    }
+
+    /// settings of a workspace folder, looked up by its name or path
+    pub fn folder_settings(&self, name: &str, path: &Path) -> Option<&Settings> {
+        self.workspace_settings.get(name).or_else(|| {
+            self.workspace_settings
+                .iter()
+                .find(|(key, _)| utils::expand_tilde(key) == path)
+                .map(|(_, settings)| settings)
+        })
+    }
+
+    /// config used in a workspace folder, only settings about completion can be overridden
+    pub fn with_folder_settings(&self, settings: &Settings) -> Config {
+        let mut config = self.clone();
+        let settings = settings.clone();
+        apply_setting!(config <- settings.max_candidates);
+        apply_setting!(config <- settings.trigger_characters);
+        apply_setting!(config <- settings.schema_trigger_character);
+        apply_setting!(config <- settings.max_tokens);
+        apply_setting!(config <- settings.always_incomplete);
+        apply_setting!(config <- settings.preselect_first);
+        apply_setting!(config <- settings.long_filter_text);
+        apply_setting!(config <- settings.show_filter_text_in_label);
+        apply_setting!(config <- settings.show_order_in_label);
+        apply_setting!(config <- settings.candidate_source);
+        apply_setting!(config <- settings.default_schemas);
+        apply_setting!(config <- settings.default_options);
+        apply_setting!(config <- settings.completion_scope);
+        apply_setting!(config <- settings.markup_skip);
+        apply_setting!(config <- settings.reading_dictionary);
+        config
+    }
+
+    /// where completion works in documents of a language
+    pub fn completion_scope_of(&self, language_id: Option<&str>) -> CompletionScope {
+        language_id
+            .and_then(|id| self.completion_scope.get(id))
+            .or_else(|| self.completion_scope.get("*"))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// find default schema of a document, languageId goes first, then the longest matched glob
+    pub fn default_schema(&self, language_id: Option<&str>, path: &str) -> Option<&str> {
+        let by_language = language_id.and_then(|id| self.default_schemas.get(id));
+        let by_glob = || {
+            self.default_schemas
+                .iter()
+                .filter(|(pattern, _)| utils::is_glob(pattern))
+                .filter(|(pattern, _)| {
+                    let pattern = utils::expand_tilde(pattern);
+                    utils::glob_match(&pattern.to_string_lossy(), path)
+                })
+                .max_by_key(|(pattern, _)| pattern.len())
+                .map(|(_, schema)| schema)
+        };
+        by_language.or_else(by_glob).map(|s| s.as_str())
+    }
+}
+
 #[test]
 fn test_default_config() {
     let config: Config = Default::default();
@@ -177,6 +453,7 @@ fn test_default_config() {
     assert_eq!(config.shared_data_dir, default_shared_data_dir());
     assert_eq!(config.user_data_dir, default_user_data_dir());
     assert_eq!(config.log_dir, default_log_dir());
+    assert_eq!(config.log_level, default_log_level());
     assert_eq!(config.max_candidates, default_max_candidates());
     assert_eq!(config.trigger_characters, default_trigger_characters());
     assert_eq!(
@@ -185,9 +462,18 @@ fn test_default_config() {
     );
     assert_eq!(config.always_incomplete, default_always_incomplete());
     assert_eq!(config.max_tokens, default_max_tokens());
+    assert_eq!(config.candidate_source, default_candidate_source());
+    assert_eq!(config.default_schemas, default_default_schemas());
+    assert_eq!(config.default_options, default_default_options());
+    assert_eq!(config.auto_deploy, default_auto_deploy());
+    assert_eq!(config.completion_scope, default_completion_scope());
+    assert_eq!(config.markup_skip, default_markup_skip());
+    assert!(config.workspace_settings.is_empty());
+    assert_eq!(config.reading_dictionary, default_reading_dictionary());
 }
 
 #[test]
+#[allow(clippy::bool_assert_comparison)]
 fn test_apply_settings() {
     let mut config: Config = Default::default();
     let settings: Settings = Settings {
@@ -202,6 +488,7 @@ fn test_apply_settings() {
         long_filter_text: None,
         show_filter_text_in_label: Some(true),
         show_order_in_label: Some(false),
+        ..Default::default()
     };
     // apply settings with macro
     let mut test_val = vec!["baz".to_string()];
@@ -227,3 +514,124 @@ fn test_apply_settings() {
     assert_eq!(config.show_order_in_label, false);
     assert_eq!(test_val, vec!["foo".to_string()]);
 }
+
+#[test]
+fn test_config_from_object() {
+    let map = match serde_json::json!({
+        "max_candidates": "9",
+        "trigger_characters": [">"],
+        "candidate_source": "all",
+        "foo": 1,
+    }) {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    };
+    let (config, warnings) = Config::from_object(map);
+    assert_eq!(config.max_candidates, default_max_candidates());
+    assert_eq!(config.trigger_characters, vec![">".to_string()]);
+    assert_eq!(config.candidate_source, CandidateSource::All);
+    assert_eq!(warnings.len(), 2);
+}
+
+#[test]
+fn test_completion_scope_of() {
+    let mut config = Config::default();
+    assert_eq!(
+        config.completion_scope_of(Some("rust")),
+        CompletionScope::All
+    );
+    config.completion_scope = HashMap::from([
+        ("*".to_string(), CompletionScope::CommentString),
+        ("markdown".to_string(), CompletionScope::All),
+    ]);
+    assert_eq!(
+        config.completion_scope_of(Some("rust")),
+        CompletionScope::CommentString
+    );
+    assert_eq!(
+        config.completion_scope_of(Some("markdown")),
+        CompletionScope::All
+    );
+    assert_eq!(
+        config.completion_scope_of(None),
+        CompletionScope::CommentString
+    );
+}
+
+#[test]
+fn test_folder_settings() {
+    let notes = Settings {
+        trigger_characters: Some(Vec::new()),
+        auto_deploy: Some(false),
+        reading_dictionary: Some("luna_pinyin".to_string()),
+        ..Default::default()
+    };
+    let code = Settings {
+        trigger_characters: Some(vec![">".to_string()]),
+        ..Default::default()
+    };
+    let config = Config {
+        workspace_settings: HashMap::from([
+            ("notes".to_string(), notes),
+            ("/home/user/code".to_string(), code),
+        ]),
+        ..Default::default()
+    };
+    let path = Path::new("/home/user/code");
+    let settings = config.folder_settings("code", path).unwrap();
+    let folder_config = config.with_folder_settings(settings);
+    assert_eq!(folder_config.trigger_characters, vec![">".to_string()]);
+    let settings = config.folder_settings("notes", Path::new("/tmp")).unwrap();
+    let folder_config = config.with_folder_settings(settings);
+    assert!(folder_config.trigger_characters.is_empty());
+    assert_eq!(folder_config.reading_dictionary, "luna_pinyin");
+    // not a setting about completion or hover
+    assert!(folder_config.auto_deploy);
+    assert!(config.folder_settings("other", Path::new("/tmp")).is_none());
+}
+
+#[test]
+fn test_read_config_file() {
+    let dir = std::env::temp_dir();
+    let toml_file = dir.join("rime-ls-test-config.toml");
+    std::fs::write(
+        &toml_file,
+        "max_candidates = 5\n[default_options]\nfull_shape = true\n",
+    )
+    .unwrap();
+    let map = read_config_file(Some(&toml_file)).unwrap();
+    assert_eq!(map["max_candidates"], 5);
+    assert_eq!(map["default_options"]["full_shape"], true);
+    let json_file = dir.join("rime-ls-test-config.json");
+    std::fs::write(&json_file, "[]").unwrap();
+    assert!(read_config_file(Some(&json_file)).is_err());
+    let _ = std::fs::remove_file(toml_file);
+    let _ = std::fs::remove_file(json_file);
+}
+
+#[test]
+fn test_default_schema() {
+    let config = Config {
+        default_schemas: HashMap::from([
+            ("markdown".to_string(), "luna_pinyin".to_string()),
+            ("**".to_string(), "double_pinyin".to_string()),
+            ("/home/*/notes/**".to_string(), "cangjie5".to_string()),
+        ]),
+        ..Default::default()
+    };
+    let path = "/home/user/code/main.rs";
+    assert_eq!(
+        config.default_schema(Some("rust"), path),
+        Some("double_pinyin")
+    );
+    let path = "/home/user/notes/a.txt";
+    assert_eq!(config.default_schema(None, path), Some("cangjie5"));
+    assert_eq!(
+        config.default_schema(Some("markdown"), path),
+        Some("luna_pinyin")
+    );
+    assert_eq!(
+        Config::default().default_schema(Some("markdown"), path),
+        None
+    );
+}
@@ -27,3 +27,9 @@ pub static AUTO_TRIGGER_RE: Lazy<Regex> = Lazy::new(|| Regex::new(AUTO_TRIGGER_P
 pub const KEY_BACKSPACE: i32 = 0xff08;
 pub const KEY_F4: i32 = 0xffc1;
 pub const KEY_ESCAPE: i32 = 0xff1b;
+
+// buffer size of schema id returned by rime
+pub const SCHEMA_ID_MAX_LEN: usize = 256;
+
+// wait for file changes to settle down before deploying
+pub const DEPLOY_DEBOUNCE_MS: u64 = 1000;
@@ -0,0 +1,234 @@
+use dashmap::DashMap;
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// max number of dictionaries kept in memory
+const MAX_DICTS: usize = 4;
+
+/// max number of characters of a word to look up around a character
+const MAX_WORD_LEN: usize = 8;
+
+/// a dictionary is loaded once in its cell, others wanting it wait for the loading
+type DictCell = Arc<OnceCell<Arc<ReverseDict>>>;
+
+/// dictionaries used recently, the last one is used latest, cleared when rime is re-deployed
+static DICTS: Lazy<Mutex<Vec<(String, DictCell)>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// dictionary used by each schema, cleared when rime is re-deployed
+static SCHEMA_DICTS: Lazy<DashMap<String, Option<String>>> = Lazy::new(DashMap::new);
+
+/// codes of words in a rime dictionary, for reverse lookup
+///
+/// librime has no API for reverse lookup, so source `*.dict.yaml` files are read,
+/// dictionaries that are only compiled are not supported
+#[derive(Debug, Default)]
+pub struct ReverseDict {
+    codes: HashMap<String, Vec<String>>,
+}
+
+impl ReverseDict {
+    /// load `<name>.dict.yaml` and tables imported by it, former dirs go first
+    pub fn load(name: &str, dirs: &[&Path]) -> ReverseDict {
+        let mut dict = ReverseDict::default();
+        dict.load_table(name, dirs, &mut HashSet::new());
+        dict
+    }
+
+    fn load_table(&mut self, name: &str, dirs: &[&Path], loaded: &mut HashSet<String>) {
+        // tables may import each other
+        if !loaded.insert(name.to_owned()) {
+            return;
+        }
+        let file_name = format!("{name}.dict.yaml");
+        let content = dirs
+            .iter()
+            .find_map(|dir| std::fs::read_to_string(dir.join(&file_name)).ok());
+        if let Some(content) = content {
+            for table in self.parse(&content) {
+                self.load_table(&table, dirs, loaded);
+            }
+        }
+    }
+
+    /// add entries of a dict.yaml, return names of imported tables
+    fn parse(&mut self, content: &str) -> Vec<String> {
+        let mut lines = content.lines();
+        // only keys needed are read from yaml header, which ends with `...`
+        let mut imports = Vec::new();
+        let mut columns = Vec::new();
+        let mut key = "";
+        for line in lines.by_ref() {
+            let line = line.split('#').next().unwrap_or_default().trim_end();
+            if line == "..." {
+                break;
+            }
+            match line.trim_start().strip_prefix("- ") {
+                Some(item) => {
+                    let item = item.trim().trim_matches(|c| c == '"' || c == '\'');
+                    match key {
+                        "import_tables" => imports.push(item.to_owned()),
+                        "columns" => columns.push(item),
+                        _ => (),
+                    }
+                }
+                None if !line.starts_with(' ') => {
+                    key = line.split(':').next().unwrap_or_default().trim();
+                }
+                None => (),
+            }
+        }
+        let column = |name: &str, default: usize| match columns.is_empty() {
+            true => Some(default),
+            false => columns.iter().position(|c| *c == name),
+        };
+        let (text_column, code_column) = match (column("text", 0), column("code", 1)) {
+            (Some(text), Some(code)) => (text, code),
+            // codes of words are made by rules, nothing to look up
+            _ => return imports,
+        };
+        for line in lines {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            if let (Some(text), Some(code)) = (fields.get(text_column), fields.get(code_column)) {
+                let codes = self.codes.entry(text.to_string()).or_default();
+                if !code.is_empty() && !codes.iter().any(|c| c == code) {
+                    codes.push(code.to_string());
+                }
+            }
+        }
+        imports
+    }
+
+    /// codes of a word, in order of the dictionary
+    pub fn lookup(&self, text: &str) -> &[String] {
+        self.codes.get(text).map_or(&[], |codes| codes.as_slice())
+    }
+
+    /// range of the longest word in `chars` that covers `chars[at]`, or just the character
+    pub fn longest_word(&self, chars: &[char], at: usize) -> Range<usize> {
+        let mut longest = at..at + 1;
+        for start in at.saturating_sub(MAX_WORD_LEN - 1)..=at {
+            let max_end = chars.len().min(start + MAX_WORD_LEN);
+            // earlier words win when they are as long
+            let found = (at + 1..=max_end)
+                .rev()
+                .take_while(|end| end - start > longest.len())
+                .find(|end| {
+                    !self
+                        .lookup(&chars[start..*end].iter().collect::<String>())
+                        .is_empty()
+                });
+            if let Some(end) = found {
+                longest = start..end;
+            }
+        }
+        longest
+    }
+}
+
+/// get a loaded dictionary, or load it from data dirs
+pub fn get(name: &str, dirs: &[&Path]) -> Arc<ReverseDict> {
+    let cell = {
+        let mut dicts = DICTS.lock().unwrap();
+        let cell = match dicts.iter().position(|(n, _)| n == name) {
+            Some(i) => dicts.remove(i).1,
+            None => Arc::default(),
+        };
+        dicts.push((name.to_owned(), Arc::clone(&cell)));
+        if dicts.len() > MAX_DICTS {
+            dicts.remove(0);
+        }
+        cell
+    };
+    // loading takes a while, the list is not locked meanwhile
+    Arc::clone(cell.get_or_init(|| Arc::new(ReverseDict::load(name, dirs))))
+}
+
+/// dictionary of a schema, `find` reads it from schema when it is not cached
+pub fn of_schema(schema_id: &str, find: impl FnOnce() -> Option<String>) -> Option<String> {
+    if let Some(name) = SCHEMA_DICTS.get(schema_id) {
+        return name.clone();
+    }
+    let name = find();
+    SCHEMA_DICTS.insert(schema_id.to_owned(), name.clone());
+    name
+}
+
+/// forget loaded dictionaries, they may be changed
+pub fn clear() {
+    DICTS.lock().unwrap().clear();
+    SCHEMA_DICTS.clear();
+}
+
+#[test]
+fn test_parse() {
+    let mut dict = ReverseDict::default();
+    let imports = dict.parse(concat!(
+        "# comment\n---\nname: test\nimport_tables:\n  - base # comment\n  - \"extra\"\n",
+        "columns:\n  - code\n  - text\n...\n\n",
+        "hang\t行\nxing\t行\nxing\t行\n# 好\thao\nni hao\t你好\n",
+    ));
+    assert_eq!(imports, vec!["base", "extra"]);
+    assert_eq!(dict.lookup("行"), ["hang", "xing"]);
+    assert_eq!(dict.lookup("你好"), ["ni hao"]);
+    assert!(dict.lookup("好").is_empty());
+    // no code column
+    let mut dict = ReverseDict::default();
+    dict.parse("---\ncolumns:\n  - text\n  - weight\n...\n你\t100\n");
+    assert!(dict.lookup("你").is_empty());
+}
+
+#[test]
+fn test_longest_word() {
+    let mut dict = ReverseDict::default();
+    dict.parse("---\n...\n你好\tni hao\n好人\thao ren\n人民\tren min\n好人好事\tx\n");
+    let chars: Vec<char> = "你好人民好人好事".chars().collect();
+    assert_eq!(dict.longest_word(&chars, 0), 0..2);
+    assert_eq!(dict.longest_word(&chars, 1), 0..2);
+    assert_eq!(dict.longest_word(&chars, 3), 2..4);
+    assert_eq!(dict.longest_word(&chars, 5), 4..8);
+    let chars: Vec<char> = "的".chars().collect();
+    assert_eq!(dict.longest_word(&chars, 0), 0..1);
+}
+
+#[test]
+fn test_load() {
+    let dir = std::env::temp_dir().join("rime-ls-test-dict");
+    let _ = std::fs::create_dir_all(&dir);
+    let main = "---\nname: main\nimport_tables:\n  - main\n  - base\n...\n你\tni\t1\n";
+    std::fs::write(dir.join("main.dict.yaml"), main).unwrap();
+    std::fs::write(dir.join("base.dict.yaml"), "---\n...\n你\tnǐ\n好\thao\n").unwrap();
+    let dict = ReverseDict::load("main", &[&dir]);
+    assert_eq!(dict.lookup("你"), ["ni", "nǐ"]);
+    assert_eq!(dict.lookup("好"), ["hao"]);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_get() {
+    let dir = std::env::temp_dir().join("rime-ls-test-dict-get");
+    let _ = std::fs::create_dir_all(&dir);
+    for i in 0..=MAX_DICTS {
+        std::fs::write(dir.join(format!("d{i}.dict.yaml")), "---\n...\n你\tni\n").unwrap();
+    }
+    let first = get("d0", &[&dir]);
+    assert!(Arc::ptr_eq(&first, &get("d0", &[&dir])));
+    assert_eq!(first.lookup("你"), ["ni"]);
+    // the least recently used one is dropped
+    for i in 1..=MAX_DICTS {
+        get(&format!("d{i}"), &[&dir]);
+    }
+    assert!(DICTS.lock().unwrap().len() <= MAX_DICTS);
+    assert!(!Arc::ptr_eq(&first, &get("d0", &[&dir])));
+    assert_eq!(
+        of_schema("test_schema", || Some("d0".into())),
+        Some("d0".into())
+    );
+    assert_eq!(of_schema("test_schema", || None), Some("d0".into()));
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -1,8 +1,10 @@
 use ouroboros::self_referencing;
 use regex::Regex;
+use std::sync::Arc;
 
+use crate::config::Config;
 use crate::consts::KEY_F4;
-use crate::rime::Rime;
+use crate::rime::{Rime, RimeError, RimeSession};
 use crate::utils::{self, DiffResult};
 
 /// struct that stores matched raw text and its matches
@@ -73,129 +75,204 @@ impl Input {
     }
 
     #[inline]
-    fn process_pinyin(&self, rime: &Rime, session_id: usize) {
+    fn process_pinyin(&self, session: &RimeSession) {
         if self.is_schema() {
             // TODO: support other shortcuts?
-            rime.process_key(session_id, KEY_F4);
+            session.process_key(KEY_F4);
         } else {
-            rime.process_str(session_id, self.pinyin());
+            session.process_str(self.pinyin());
         }
     }
 
     #[inline]
-    fn process_select(&self, rime: &Rime, session_id: usize) {
-        rime.process_str(session_id, self.select());
+    fn process_select(&self, session: &RimeSession) {
+        session.process_str(self.select());
     }
 
     /// diff current pinyin with new input, and do rime thing
-    pub fn diff_pinyin(&self, rime: &Rime, session_id: usize, new_input: &Self, refresh: bool) {
+    pub fn diff_pinyin(&self, session: &RimeSession, new_input: &Self, refresh: bool) {
         match utils::diff(self.pinyin(), new_input.pinyin()) {
-            DiffResult::Add(suffix) => rime.process_str(session_id, suffix),
+            DiffResult::Add(suffix) => session.process_str(suffix),
             DiffResult::Delete(suffix) => {
                 if refresh {
-                    rime.clear_composition(session_id);
-                    new_input.process_pinyin(rime, session_id);
-                    new_input.process_select(rime, session_id);
+                    session.clear_composition();
+                    new_input.process_pinyin(session);
+                    new_input.process_select(session);
                 }
-                rime.delete_keys(session_id, suffix.len())
+                session.delete_keys(suffix.len())
             }
             DiffResult::New => {
-                rime.clear_composition(session_id);
-                new_input.process_pinyin(rime, session_id);
+                session.clear_composition();
+                new_input.process_pinyin(session);
             }
             _ => (),
         }
     }
 
     /// diff current select with new input, and do rime thing
-    pub fn diff_select(&self, rime: &Rime, session_id: usize, new_input: &Self) {
+    pub fn diff_select(&self, session: &RimeSession, new_input: &Self) {
         match utils::diff(self.select(), new_input.select()) {
-            DiffResult::Add(suffix) => rime.process_str(session_id, suffix),
-            DiffResult::Delete(suffix) => rime.delete_keys(session_id, suffix.len()),
+            DiffResult::Add(suffix) => session.process_str(suffix),
+            DiffResult::Delete(suffix) => session.delete_keys(suffix.len()),
             DiffResult::New => {
-                rime.delete_keys(session_id, self.select().len());
-                rime.process_str(session_id, new_input.select());
+                session.delete_keys(self.select().len());
+                session.process_str(new_input.select());
             }
             _ => (),
         }
     }
 }
 
+/// how to set up a newly created session
+#[derive(Debug, Default, Clone)]
+pub struct SessionSetup {
+    /// config of the document, `default_schemas` and `default_options` are used
+    pub config: Arc<Config>,
+    /// languageId and path of the document, none if the session is not for a document
+    pub document: Option<(Option<String>, String)>,
+}
+
+impl SessionSetup {
+    /// schema to select, or the one rime remembers, only looked up when creating a session
+    pub fn schema_id(&self) -> Option<&str> {
+        let (language_id, path) = self.document.as_ref()?;
+        self.config.default_schema(language_id.as_deref(), path)
+    }
+
+    pub fn create_session(&self) -> RimeSession {
+        let session = Rime::global().create_session();
+        match self.schema_id() {
+            // unknown schema is not selected, keep the default one
+            Some(schema_id) => {
+                let _ = self.select_schema(&session, schema_id);
+            }
+            None => self.set_options(&session),
+        }
+        session
+    }
+
+    /// select a schema in a session, options are set again since rime resets them
+    pub fn select_schema(&self, session: &RimeSession, schema_id: &str) -> Result<bool, RimeError> {
+        let selected = session.select_schema(schema_id)?;
+        self.set_options(session);
+        Ok(selected)
+    }
+
+    fn set_options(&self, session: &RimeSession) {
+        for (option, value) in &self.config.default_options {
+            let _ = session.set_option(option, *value);
+        }
+    }
+}
+
 /// save input state
 pub struct InputState {
     input: Input,
-    session_id: usize,
+    session: RimeSession,
     offset: usize,
     is_incomplete: bool,
 }
 
 /// result of handling new input
 pub struct InputResult {
-    /// session id after handling new input
-    pub session_id: usize,
+    /// session after handling new input
+    pub session: RimeSession,
     /// sometimes extra offset is caused by new input
     pub extra_offset: usize,
 }
 
 impl InputState {
-    pub fn new(input: Input, session_id: usize, offset: usize, is_incomplete: bool) -> InputState {
+    pub fn new(
+        input: Input,
+        session: RimeSession,
+        offset: usize,
+        is_incomplete: bool,
+    ) -> InputState {
         InputState {
             input,
-            session_id,
+            session,
             offset,
             is_incomplete,
         }
     }
 
-    pub fn session_id(&self) -> usize {
-        self.session_id
-    }
-
     #[inline]
-    fn assemble_result(session_id: usize, pinyin: &str, raw_input: Option<String>) -> InputResult {
+    fn assemble_result(
+        session: RimeSession,
+        pinyin: &str,
+        raw_input: Option<String>,
+    ) -> InputResult {
         let extra_offset = raw_input
             .and_then(utils::option_string)
             .and_then(|rime_raw_input| pinyin.rfind(&rime_raw_input))
             .unwrap_or(0);
         InputResult {
-            session_id,
+            session,
             extra_offset,
         }
     }
 
-    pub fn first_input(new_input: &Input) -> InputResult {
-        let rime = Rime::global();
-        let session_id = rime.create_session();
+    pub fn session(&self) -> &RimeSession {
+        &self.session
+    }
+
+    /// input typed in document when the state is made
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    /// char offset of the input in document
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// make next input start a new typing, e.g. after composition is cleared by rime
+    pub fn reset(&mut self) {
+        self.is_incomplete = false;
+    }
+
+    /// start a new typing with a session that has no composition
+    fn new_typing(session: RimeSession, new_input: &Input) -> InputResult {
+        new_input.process_pinyin(&session);
+        new_input.process_select(&session);
 
-        new_input.process_pinyin(rime, session_id);
-        new_input.process_select(rime, session_id);
+        let raw_input = session.get_raw_input();
+        Self::assemble_result(session, new_input.pinyin(), raw_input)
+    }
 
-        let raw_input = rime.get_raw_input(session_id);
-        Self::assemble_result(session_id, new_input.pinyin(), raw_input)
+    pub fn first_input(new_input: &Input, setup: &SessionSetup) -> InputResult {
+        let session = setup.create_session();
+        Self::new_typing(session, new_input)
     }
 
-    fn continue_input(&self, new_input: &Input, refresh: bool) -> InputResult {
-        let rime = Rime::global();
-        let session_id = self.session_id;
+    fn continue_input(self, new_input: &Input, refresh: bool) -> InputResult {
+        let session = self.session;
         // 1. handle pinyin of new_input
-        self.input.diff_pinyin(rime, session_id, new_input, refresh);
+        self.input.diff_pinyin(&session, new_input, refresh);
         // 2. get raw input before handling select or we may get empty string
-        let raw_input = rime.get_raw_input(session_id);
+        let raw_input = session.get_raw_input();
         // 3. handle select of new_input
-        self.input.diff_select(rime, session_id, new_input);
-        Self::assemble_result(session_id, new_input.pinyin(), raw_input)
+        self.input.diff_select(&session, new_input);
+        Self::assemble_result(session, new_input.pinyin(), raw_input)
     }
 
-    pub fn apply_input(&self, new_offset: usize, input: &Input, max_tokens: usize) -> InputResult {
-        let rime = Rime::global();
+    /// consume last state and handle new input
+    pub fn apply_input(
+        self,
+        new_offset: usize,
+        input: &Input,
+        max_tokens: usize,
+        setup: &SessionSetup,
+    ) -> InputResult {
         // 1. totally new typing (create new session)
-        if !rime.find_session(self.session_id) {
-            return Self::first_input(input);
+        if !self.session.exists() {
+            return Self::first_input(input, setup);
         }
-        // 2. typing with new offset (destroy old session and create new one)
+        // 2. typing with new offset (clear old composition and reuse the session,
+        //    so that schema and options selected in this session are kept)
         if self.offset != new_offset || !self.is_incomplete {
-            rime.destroy_session(self.session_id);
-            return Self::first_input(input);
+            self.session.clear_composition();
+            return Self::new_typing(self.session, input);
         }
         // 3. continue last typing, diff and process (with last session)
         // if current pinyin len == max_tokens, force refreshing
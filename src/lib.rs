@@ -1,13 +1,19 @@
 /// handle config and settings
-mod config;
+pub mod config;
 /// const values
 mod consts;
+/// reverse lookup of rime dictionaries
+mod dict;
 /// handle user input
 mod input;
 /// librime C FFI
 pub mod rime;
+/// find comments and strings in code, code and math in markup documents
+mod scanner;
 /// helper functions
 mod utils;
+/// thread that serializes librime calls
+pub mod worker;
 
 /// impl LSP for Rime
 pub mod lsp;
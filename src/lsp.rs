@@ -1,37 +1,249 @@
 use dashmap::DashMap;
+use once_cell::sync::Lazy;
 use regex::Regex;
 use ropey::Rope;
-use serde_json::Value;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::borrow::Cow;
-use tokio::sync::RwLock;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer};
 
-use crate::config::{apply_setting, Config, Settings};
-use crate::consts::{trigger_ptn, NT_RE};
-use crate::input::{Input, InputResult, InputState};
-use crate::rime::{Candidate, Rime, RimeError, RimeResponse};
+use crate::config::{apply_setting, CandidateSource, CompletionScope, Config, Settings};
+use crate::consts::{trigger_ptn, DEPLOY_DEBOUNCE_MS, NT_RE};
+use crate::dict;
+use crate::input::{Input, InputResult, InputState, SessionSetup};
+use crate::rime::{Candidate, Rime, RimeError, RimeResponse, RimeSession};
+use crate::scanner::{self, Context, Markup, MarkupScanner, Scanner};
 use crate::utils::{self, Encoding};
+use crate::worker;
+
+/// input state of a document, locked while it is used so that its session is never duplicated
+type DocumentState = Arc<Mutex<Option<InputState>>>;
+
+/// a client connected to this server
+struct ConnectedClient {
+    name: Option<String>,
+    version: Option<String>,
+    state: Arc<DashMap<String, DocumentState>>,
+}
+
+/// all clients connected to this server, rime is shared by them
+static CLIENTS: Lazy<DashMap<usize, ConnectedClient>> = Lazy::new(DashMap::new);
+static NEXT_CLIENT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// section of settings in workspace/configuration
+const SETTINGS_SECTION: &str = "rime-ls";
+
+/// id of the registration that watches yaml files in user data dir
+const WATCH_USER_DATA_DIR: &str = "rime-ls.watch-user-data-dir";
 
 pub struct Backend {
+    id: usize,
     client: Client,
     documents: DashMap<String, Rope>,
-    state: DashMap<String, Option<InputState>>,
+    language_ids: DashMap<String, String>,
+    /// scanners of each document cached at the beginning of a line
+    scanners: DashMap<String, LineScanners>,
+    workspace_folders: RwLock<Vec<WorkspaceFolder>>,
+    state: Arc<DashMap<String, DocumentState>>,
+    /// session kept for lookups of documents without sessions, to find the default schema
+    lookup_session: Arc<Mutex<Option<RimeSession>>>,
     config: RwLock<Config>,
-    regex: RwLock<Regex>,
+    /// built from `config` and `workspace_folders` when either changes
+    document_configs: RwLock<DocumentConfigs>,
     encoding: RwLock<Encoding>,
+    capabilities: RwLock<ClientCapabilities>,
+    /// number of file changes that want a deployment, used for debouncing
+    deploy_requests: Arc<AtomicUsize>,
+    /// options given by command line
+    options: Arc<ServerOptions>,
+}
+
+/// options given by command line, shared by all connections
+#[derive(Debug, Default)]
+pub struct ServerOptions {
+    /// shared secret that client must present in initializationOptions, checked before serving
+    pub token: Option<String>,
+    /// default config from config file and command line, fields in initializationOptions override them
+    pub defaults: serde_json::Map<String, Value>,
+    /// errors of the default config file, which is skipped, shown to client on initialize
+    pub config_warnings: Vec<String>,
+}
+
+/// if the first message of a connection is `initialize` with the token in initializationOptions
+pub fn authenticate(token: &str, message: &Value) -> bool {
+    let is_initialize = message.get("method").and_then(Value::as_str) == Some("initialize");
+    is_initialize
+        && message
+            .pointer("/params/initializationOptions/token")
+            .and_then(Value::as_str)
+            .is_some_and(|t| utils::constant_time_eq(t.as_bytes(), token.as_bytes()))
+}
+
+/// effective config and trigger regex of documents
+struct DocumentConfigs {
+    /// for documents outside workspace folders
+    default: (Arc<Config>, Regex),
+    /// path of each workspace folder, with its settings applied
+    folders: Vec<(PathBuf, Arc<Config>, Regex)>,
+}
+
+impl Default for DocumentConfigs {
+    fn default() -> Self {
+        DocumentConfigs {
+            default: (Arc::new(Config::default()), NT_RE.clone()),
+            folders: Vec::new(),
+        }
+    }
+}
+
+/// scanners of a document at the beginning of a line, with char offset of the line
+#[derive(Default)]
+struct LineScanners {
+    code: Option<(usize, Scanner<'static>)>,
+    markup: Option<(usize, MarkupScanner)>,
+}
+
+impl LineScanners {
+    /// drop scanners after `offset`, where the text is changed
+    fn changed_at(&mut self, offset: usize) {
+        self.code = self.code.take().filter(|(begin, _)| *begin <= offset);
+        self.markup = self.markup.take().filter(|(begin, _)| *begin <= offset);
+    }
+}
+
+/// scan a document to `offset`, resuming from the cached scanner if it is still usable,
+/// and cache the scanner at the beginning of the offset's line
+fn resume_scanner<S: Clone>(
+    cached: &mut Option<(usize, S)>,
+    fresh: impl FnOnce() -> S,
+    is_for: impl FnOnce(&S) -> bool,
+    rope: &Rope,
+    offset: usize,
+    feed: impl Fn(&mut S, &str),
+) -> S {
+    let line_begin = rope.line_to_char(rope.char_to_line(offset));
+    let (begin, mut scanner) = match cached.take() {
+        Some((begin, scanner)) if begin <= line_begin && is_for(&scanner) => (begin, scanner),
+        _ => (0, fresh()),
+    };
+    let feed_range = |scanner: &mut S, start, end| {
+        for chunk in rope.slice(start..end).chunks() {
+            feed(scanner, chunk);
+        }
+    };
+    feed_range(&mut scanner, begin, line_begin);
+    *cached = Some((line_begin, scanner.clone()));
+    feed_range(&mut scanner, line_begin, offset);
+    scanner
+}
+
+/// regex that matches input after trigger characters
+fn trigger_regex(chars: &[String]) -> Option<Regex> {
+    let pattern = format!(trigger_ptn!(), chars.join(""));
+    (!chars.is_empty()).then(|| Regex::new(&pattern).unwrap())
+}
+
+/// data of a completion item, to find its candidate in rime later
+#[derive(Debug, Serialize, Deserialize)]
+struct CandidateData {
+    uri: String,
+    session: usize,
+    /// index in all pages
+    index: usize,
+    text: String,
+    /// code typed in document
+    input: String,
+    /// char offset of the input in document
+    offset: usize,
+    preedit: String,
+}
+
+/// destroy all sessions and deploy rime
+async fn deploy_rime(state: &DashMap<String, DocumentState>) -> std::result::Result<(), RimeError> {
+    state.clear();
+    worker::call(|| Rime::global().deploy())
+        .await
+        .and_then(|r| r)
+}
+
+async fn create_work_done_progress(
+    client: &Client,
+    token: NumberOrString,
+) -> Result<NumberOrString> {
+    if let Err(e) = client
+        .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+            token: token.clone(),
+        })
+        .await
+    {
+        client.log_message(MessageType::WARNING, e).await;
+        return Err(tower_lsp::jsonrpc::Error::internal_error());
+    }
+    Ok(token)
+}
+
+async fn notify_work_begin(client: &Client, token: NumberOrString, message: &str) {
+    // begin
+    client
+        .send_notification::<notification::Progress>(ProgressParams {
+            token,
+            value: ProgressParamsValue::WorkDone(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: message.to_string(),
+                ..Default::default()
+            })),
+        })
+        .await;
+}
+
+async fn notify_work_done(client: &Client, token: NumberOrString, message: &str) {
+    client
+        .send_notification::<notification::Progress>(ProgressParams {
+            token,
+            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(WorkDoneProgressEnd {
+                message: Some(message.to_string()),
+            })),
+        })
+        .await;
 }
 
 impl Backend {
     pub fn new(client: Client) -> Backend {
+        Backend::with_options(client, Arc::default())
+    }
+
+    pub fn with_options(client: Client, options: Arc<ServerOptions>) -> Backend {
+        let id = NEXT_CLIENT_ID.fetch_add(1, Ordering::SeqCst);
+        let state = Arc::new(DashMap::new());
+        CLIENTS.insert(
+            id,
+            ConnectedClient {
+                name: None,
+                version: None,
+                state: Arc::clone(&state),
+            },
+        );
         Backend {
+            id,
             client,
             documents: DashMap::new(),
-            state: DashMap::new(),
+            language_ids: DashMap::new(),
+            scanners: DashMap::new(),
+            workspace_folders: RwLock::new(Vec::new()),
+            state,
+            lookup_session: Arc::default(),
             config: RwLock::new(Config::default()),
-            regex: RwLock::new(NT_RE.clone()),
+            document_configs: RwLock::new(DocumentConfigs::default()),
             encoding: RwLock::new(Encoding::default()),
+            capabilities: RwLock::new(ClientCapabilities::default()),
+            deploy_requests: Arc::new(AtomicUsize::new(0)),
+            options,
         }
     }
 
@@ -41,36 +253,141 @@ impl Backend {
         let shared_data_dir = utils::expand_tilde(&config.shared_data_dir);
         let user_data_dir = utils::expand_tilde(&config.user_data_dir);
         let log_dir = utils::expand_tilde(&config.log_dir);
-        // to str
-        let shared_data_dir = shared_data_dir.to_str().unwrap();
-        let user_data_dir = user_data_dir.to_str().unwrap();
-        let log_dir = log_dir.to_str().unwrap();
-        // compile regex
-        let trigger_characters = &config.trigger_characters;
-        self.compile_regex(trigger_characters).await;
+        let log_level = config.log_level as i32;
         // init rime
-        match Rime::init(shared_data_dir, user_data_dir, log_dir) {
+        let dirs = [
+            (
+                "shared_data_dir",
+                shared_data_dir.to_string_lossy().to_string(),
+            ),
+            ("user_data_dir", user_data_dir.to_string_lossy().to_string()),
+            ("log_dir", log_dir.to_string_lossy().to_string()),
+        ];
+        let res = worker::call(move || {
+            let shared_data_dir = shared_data_dir.to_str().unwrap();
+            let user_data_dir = user_data_dir.to_str().unwrap();
+            let log_dir = log_dir.to_str().unwrap();
+            Rime::init(shared_data_dir, user_data_dir, log_dir, log_level)
+        })
+        .await
+        .and_then(|r| r);
+        match res {
             Err(RimeError::AlreadyInitialized) => {
                 let info = "Use an initialized rime instance.";
                 self.client.log_message(MessageType::INFO, info).await;
+                self.check_data_dirs(&dirs).await;
                 Ok(())
             }
             r => r,
         }
     }
 
-    async fn init_config(&self, params: Value) {
-        let mut config = self.config.write().await;
-        let new_cfg: Config = serde_json::from_value(params).unwrap_or_default();
-        *config = new_cfg;
+    /// warn client if its data dirs differ from the ones of shared rime instance
+    async fn check_data_dirs(&self, dirs: &[(&str, String); 3]) {
+        let rime = Rime::global();
+        let used = [
+            rime.shared_data_dir(),
+            rime.user_data_dir(),
+            rime.log_dir().to_owned(),
+        ];
+        for ((name, wanted), used) in dirs.iter().zip(used) {
+            if *wanted != used {
+                let msg = format!(
+                    "{name} \"{wanted}\" is ignored, rime is already initialized with \"{used}\" by another client"
+                );
+                self.client.log_message(MessageType::WARNING, &msg).await;
+                self.client.show_message(MessageType::WARNING, msg).await;
+            }
+        }
+    }
+
+    /// information of this server and all connected clients
+    fn server_info(&self) -> Value {
+        let clients: Vec<Value> = CLIENTS
+            .iter()
+            .map(|kv| {
+                let client = kv.value();
+                // a locked state is being used
+                let sessions = client
+                    .state
+                    .iter()
+                    .filter(|s| s.try_lock().map(|s| s.is_some()).unwrap_or(true))
+                    .count();
+                json!({
+                    "id": kv.key(),
+                    "name": client.name,
+                    "version": client.version,
+                    "sessions": sessions,
+                    "current": *kv.key() == self.id,
+                })
+            })
+            .collect();
+        let rime = Rime::is_initialized().then(|| {
+            let rime = Rime::global();
+            json!({
+                "shared_data_dir": rime.shared_data_dir(),
+                "user_data_dir": rime.user_data_dir(),
+                "log_dir": rime.log_dir(),
+                "ready": rime.is_ready(),
+            })
+        });
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "rime": rime,
+            "clients": clients,
+        })
     }
 
-    async fn compile_regex(&self, chars: &[String]) {
-        if !chars.is_empty() {
-            let mut regex = self.regex.write().await;
-            let pattern = format!(trigger_ptn!(), chars.join(""));
-            *regex = Regex::new(&pattern).unwrap();
+    async fn init_config(&self, params: Option<Value>) {
+        // fields in initializationOptions override defaults of server
+        let mut merged = self.options.defaults.clone();
+        if let Some(Value::Object(params)) = params {
+            merged.extend(params);
         }
+        // token is only used for authentication
+        merged.remove("token");
+        let (new_cfg, warnings) = Config::from_object(merged);
+        let warnings: Vec<String> = self
+            .options
+            .config_warnings
+            .iter()
+            .cloned()
+            .chain(warnings)
+            .collect();
+        if !warnings.is_empty() {
+            for warning in &warnings {
+                self.client.log_message(MessageType::WARNING, warning).await;
+            }
+            let msg = format!("rime-ls: {}", warnings.join("; "));
+            self.client.show_message(MessageType::WARNING, msg).await;
+        }
+        *self.config.write().await = new_cfg;
+    }
+
+    /// build config of each workspace folder again, after settings or folders are changed
+    async fn update_document_configs(&self) {
+        let config = self.config.read().await;
+        let regex = trigger_regex(&config.trigger_characters).unwrap_or_else(|| NT_RE.clone());
+        let default = (Arc::new(config.clone()), regex);
+        let folders = self.workspace_folders.read().await;
+        let folders = folders
+            .iter()
+            .map(|folder| {
+                let path = PathBuf::from(utils::uri_to_path(&folder.uri));
+                let (config, regex) = match config.folder_settings(&folder.name, &path) {
+                    Some(settings) => {
+                        let regex = match settings.trigger_characters {
+                            Some(ref chars) => trigger_regex(chars).unwrap_or(default.1.clone()),
+                            None => default.1.clone(),
+                        };
+                        (Arc::new(config.with_folder_settings(settings)), regex)
+                    }
+                    None => default.clone(),
+                };
+                (path, config, regex)
+            })
+            .collect();
+        *self.document_configs.write().await = DocumentConfigs { default, folders };
     }
 
     async fn apply_settings(&self, params: Value) {
@@ -84,12 +401,16 @@ impl Backend {
         };
 
         let mut config = self.config.write().await;
+        let dirs_changed = settings.shared_data_dir.is_some()
+            || settings.user_data_dir.is_some()
+            || settings.log_dir.is_some();
+        apply_setting!(config <- settings.shared_data_dir);
+        apply_setting!(config <- settings.user_data_dir);
+        apply_setting!(config <- settings.log_dir);
         apply_setting!(config <- settings.enabled);
         apply_setting!(config <- settings.max_candidates);
         apply_setting!(config <- settings.paging_characters);
-        apply_setting!(config <- settings.trigger_characters, |v| {
-            self.compile_regex(&v).await;
-        });
+        apply_setting!(config <- settings.trigger_characters);
         apply_setting!(config <- settings.schema_trigger_character);
         apply_setting!(config <- settings.max_tokens);
         apply_setting!(config <- settings.always_incomplete);
@@ -97,61 +418,663 @@ impl Backend {
         apply_setting!(config <- settings.long_filter_text);
         apply_setting!(config <- settings.show_filter_text_in_label);
         apply_setting!(config <- settings.show_order_in_label);
+        apply_setting!(config <- settings.candidate_source);
+        apply_setting!(config <- settings.default_schemas);
+        apply_setting!(config <- settings.default_options);
+        apply_setting!(config <- settings.auto_deploy);
+        apply_setting!(config <- settings.completion_scope);
+        apply_setting!(config <- settings.markup_skip);
+        apply_setting!(config <- settings.workspace_settings);
+        apply_setting!(config <- settings.reading_dictionary);
+        drop(config);
+        self.update_document_configs().await;
+        if dirs_changed {
+            self.switch_data_dirs().await;
+        }
     }
 
-    async fn create_work_done_progress(&self, token: NumberOrString) -> Result<NumberOrString> {
-        if let Err(e) = self
-            .client
-            .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
-                token: token.clone(),
+    /// pull settings with workspace/configuration if client supports it
+    async fn pull_settings(&self) {
+        let supported = self
+            .capabilities
+            .read()
+            .await
+            .workspace
+            .as_ref()
+            .and_then(|w| w.configuration)
+            .unwrap_or(false);
+        if !supported {
+            return;
+        }
+        let items = vec![ConfigurationItem {
+            scope_uri: None,
+            section: Some(SETTINGS_SECTION.to_string()),
+        }];
+        match self.client.configuration(items).await {
+            // client returns null if section is not configured
+            Ok(values) => {
+                if let Some(settings @ Value::Object(_)) = values.into_iter().next() {
+                    self.apply_settings(settings).await;
+                }
+            }
+            Err(e) => self.client.log_message(MessageType::WARNING, e).await,
+        }
+    }
+
+    /// re-initialize rime if data dirs in config differ from the ones in use
+    async fn switch_data_dirs(&self) {
+        if !Rime::is_initialized() {
+            return;
+        }
+        let (shared_data_dir, user_data_dir, log_dir, auto_deploy) = {
+            let config = self.config.read().await;
+            let expand = |p| utils::expand_tilde(p).to_string_lossy().to_string();
+            (
+                expand(&config.shared_data_dir),
+                expand(&config.user_data_dir),
+                expand(&config.log_dir),
+                config.auto_deploy,
+            )
+        };
+        let rime = Rime::global();
+        if log_dir != rime.log_dir() {
+            let msg = "log_dir is changed, it takes effect after restarting rime-ls";
+            self.client.show_message(MessageType::WARNING, msg).await;
+        }
+        if shared_data_dir == rime.shared_data_dir() && user_data_dir == rime.user_data_dir() {
+            return;
+        }
+        // re-initializing takes a while, run it in background so that notifications are not blocked
+        let client = self.client.clone();
+        let state = Arc::clone(&self.state);
+        tokio::spawn(async move {
+            let command = "rime-ls.switch-data-dirs";
+            let token = NumberOrString::String(command.to_string());
+            let token = create_work_done_progress(&client, token).await.ok();
+            if let Some(token) = token.clone() {
+                notify_work_begin(&client, token, "Rime is switching data dirs").await;
+            }
+            state.clear();
+            let res = worker::call(move || {
+                Rime::global().switch_data_dirs(&shared_data_dir, &user_data_dir)
             })
             .await
+            .and_then(|r| r);
+            let status = match res {
+                Ok(_) => "Rime is Ready.",
+                Err(e) => {
+                    client.log_message(MessageType::ERROR, &e).await;
+                    client.show_message(MessageType::ERROR, e).await;
+                    "Rime failed to switch data dirs."
+                }
+            };
+            if let Some(token) = token {
+                notify_work_done(&client, token, status).await;
+            }
+        });
+        if auto_deploy {
+            self.unwatch_user_data_dir().await;
+            self.watch_user_data_dir().await;
+        }
+    }
+
+    /// run a job on rime thread with every live input state
+    async fn with_all_states<F, R>(&self, f: F) -> std::result::Result<Vec<R>, RimeError>
+    where
+        F: Fn(&mut InputState) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        // lock states in a fixed order, so that concurrent jobs never deadlock
+        let mut slots: Vec<(String, DocumentState)> = self
+            .state
+            .iter()
+            .map(|kv| (kv.key().clone(), Arc::clone(kv.value())))
+            .collect();
+        slots.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut states = Vec::with_capacity(slots.len());
+        for (_, slot) in slots {
+            states.push(slot.lock_owned().await);
+        }
+        worker::call(move || {
+            states
+                .iter_mut()
+                .filter_map(|state| state.as_mut())
+                .map(f)
+                .collect()
+        })
+        .await
+    }
+
+    /// schema of the session of a document, or the one a new session would use
+    async fn schema_of(&self, uri: Option<&Url>) -> std::result::Result<Option<String>, RimeError> {
+        let config = match uri {
+            Some(uri) => self.document_config(uri).await.0,
+            None => self.default_config().await,
+        };
+        let setup = self.session_setup(config, uri);
+        let slot = uri
+            .and_then(|uri| self.state.get(uri.as_str()))
+            .map(|slot| Arc::clone(&slot));
+        let state = match slot {
+            Some(slot) => Some(slot.lock_owned().await),
+            None => None,
+        };
+        let mut lookup_session = Arc::clone(&self.lookup_session).lock_owned().await;
+        worker::call(move || {
+            // schema of document session goes first, then the one a new session would select
+            let session_schema = state
+                .as_deref()
+                .and_then(Option::as_ref)
+                .and_then(|state| state.session().get_current_schema());
+            let configured = || {
+                let schema_id = setup.schema_id()?;
+                Rime::global()
+                    .has_schema(schema_id)
+                    .then(|| schema_id.to_owned())
+            };
+            match session_schema.or_else(configured) {
+                Some(schema_id) => Some(schema_id),
+                None => {
+                    if !lookup_session.as_ref().is_some_and(RimeSession::exists) {
+                        *lookup_session = Some(Rime::global().create_session());
+                    }
+                    lookup_session.as_ref()?.get_current_schema()
+                }
+            }
+        })
+        .await
+    }
+
+    /// dictionary of the schema used by a document, or the default schema
+    async fn schema_dictionary(&self, uri: Option<&Url>) -> Option<String> {
+        if !Rime::is_initialized() || !Rime::global().is_ready() {
+            return None;
+        }
+        let found = match self.schema_of(uri).await {
+            Ok(Some(schema_id)) => {
+                worker::call(move || {
+                    dict::of_schema(&schema_id, || {
+                        Rime::global().get_schema_string(&schema_id, "translator/dictionary")
+                    })
+                })
+                .await
+            }
+            Ok(None) => return None,
+            Err(e) => Err(e),
+        };
+        match found {
+            Ok(found) => found,
+            Err(e) => {
+                self.client.log_message(MessageType::ERROR, &e).await;
+                None
+            }
+        }
+    }
+
+    /// run `f` with a dictionary, which is loaded if it is not in memory
+    async fn with_dict<T: Send + 'static>(
+        &self,
+        dictionary: String,
+        f: impl FnOnce(&dict::ReverseDict) -> T + Send + 'static,
+    ) -> Option<T> {
+        let rime = Rime::global();
+        let (user_data_dir, shared_data_dir) = (rime.user_data_dir(), rime.shared_data_dir());
+        // loading a large dictionary takes a while, keep it off rime thread
+        tokio::task::spawn_blocking(move || {
+            let dirs = [Path::new(&user_data_dir), Path::new(&shared_data_dir)];
+            f(&dict::get(&dictionary, &dirs))
+        })
+        .await
+        .ok()
+    }
+
+    /// codes of each word in a dictionary
+    async fn lookup_codes(&self, dictionary: String, words: Vec<String>) -> Vec<Vec<String>> {
+        let codes = self.with_dict(dictionary, move |dict| {
+            words.iter().map(|w| dict.lookup(w).to_vec()).collect()
+        });
+        codes.await.unwrap_or_default()
+    }
+
+    /// Chinese characters around position of a document, char offset of the first one,
+    /// and index of the one at position
+    fn han_around(
+        &self,
+        uri: &Url,
+        position: Position,
+        encoding: Encoding,
+    ) -> Option<(Vec<char>, usize, usize)> {
+        let rope = self.documents.get(uri.as_str())?;
+        let offset = utils::position_to_offset(&rope, position, encoding)?;
+        let line_begin = rope.try_line_to_char(position.line as usize).ok()?;
+        let chars: Vec<char> = rope.get_line(position.line as usize)?.chars().collect();
+        let index = offset - line_begin;
+        let range = utils::han_range(&chars, index)?;
+        // cursor may be just after the characters
+        let at = index.min(range.end - 1) - range.start;
+        Some((chars[range.clone()].to_vec(), line_begin + range.start, at))
+    }
+
+    /// readings of the longest word around `chars[at]` and its range in `chars`
+    async fn readings_of(
+        &self,
+        uri: &Url,
+        chars: Vec<char>,
+        at: usize,
+    ) -> Option<(String, std::ops::Range<usize>)> {
+        let (config, _) = self.document_config(uri).await;
+        let dictionary = match config.reading_dictionary.is_empty() {
+            true => self.schema_dictionary(Some(uri)).await?,
+            false => config.reading_dictionary.clone(),
+        };
+        // prose has no spaces, so only a word is read instead of the whole clause
+        let found = self.with_dict(dictionary, move |dict| {
+            let range = dict.longest_word(&chars, at);
+            let text: String = chars[range.clone()].iter().collect();
+            let codes = dict.lookup(&text).to_vec();
+            (text, codes, range)
+        });
+        match found.await? {
+            (_, codes, _) if codes.is_empty() => None,
+            (text, codes, range) => Some((format!("**{text}** {}", codes.join(" / ")), range)),
+        }
+    }
+
+    /// codes of Chinese characters under cursor, or in given text
+    async fn reverse_lookup(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let (uri, text) = match arguments.into_iter().next() {
+            Some(Value::String(text)) => (None, text),
+            Some(arg) => match serde_json::from_value::<TextDocumentPositionParams>(arg) {
+                Ok(params) => {
+                    let uri = params.text_document.uri;
+                    let encoding = *self.encoding.read().await;
+                    let text = self
+                        .han_around(&uri, params.position, encoding)
+                        .map(|(chars, _, _)| chars.into_iter().collect());
+                    (Some(uri), text.unwrap_or_default())
+                }
+                Err(e) => return Err(tower_lsp::jsonrpc::Error::invalid_params(e.to_string())),
+            },
+            None => {
+                let msg = "rime-ls.reverse-lookup requires text or text document position";
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(msg));
+            }
+        };
+        let chars: Vec<String> = text.chars().map(String::from).collect();
+        let codes = match self.schema_dictionary(uri.as_ref()).await {
+            Some(dictionary) => self.lookup_codes(dictionary, chars.clone()).await,
+            None => Vec::new(),
+        };
+        let results: Vec<Value> = chars
+            .iter()
+            .enumerate()
+            .map(|(i, c)| json!({ "text": c, "codes": codes.get(i).cloned().unwrap_or_default() }))
+            .collect();
+        Ok(Some(Value::from(results)))
+    }
+
+    /// how to set up a new session for a document, or for no document
+    fn session_setup(&self, config: Arc<Config>, uri: Option<&Url>) -> SessionSetup {
+        let document = uri.map(|uri| {
+            let language_id = self.language_ids.get(uri.as_str());
+            let language_id = language_id.map(|s| s.value().clone());
+            (language_id, utils::uri_to_path(uri))
+        });
+        SessionSetup { config, document }
+    }
+
+    /// config of documents outside workspace folders
+    async fn default_config(&self) -> Arc<Config> {
+        Arc::clone(&self.document_configs.read().await.default.0)
+    }
+
+    /// config of a document, settings of its workspace folder are applied
+    async fn document_config(&self, uri: &Url) -> (Arc<Config>, Regex) {
+        let path = utils::uri_to_path(uri);
+        let configs = self.document_configs.read().await;
+        // the innermost folder wins when folders are nested
+        let (config, regex) = configs
+            .folders
+            .iter()
+            .filter(|(folder_path, _, _)| Path::new(&path).starts_with(folder_path))
+            .max_by_key(|(folder_path, _, _)| folder_path.as_os_str().len())
+            .map_or(
+                (&configs.default.0, &configs.default.1),
+                |(_, config, regex)| (config, regex),
+            );
+        (Arc::clone(config), regex.clone())
+    }
+
+    /// if completion works at the offset, according to `completion_scope` and `markup_skip`
+    fn in_completion_scope(&self, config: &Config, uri: &Url, rope: &Rope, offset: usize) -> bool {
+        let language_id = self.language_ids.get(uri.as_str());
+        let language_id = language_id.as_ref().map(|s| s.as_str());
+        // languages that are unknown to scanner are not checked
+        let syntax = match config.completion_scope_of(language_id) {
+            CompletionScope::All => None,
+            CompletionScope::CommentString => language_id.and_then(scanner::syntax_of),
+        };
+        let markup_skip = language_id.and_then(|id| Some((id, config.markup_skip.get(id)?)));
+        if (syntax.is_none() && markup_skip.is_none()) || offset > rope.len_chars() {
+            return true;
+        }
+        if let Some(syntax) = syntax {
+            // guard of the entry is dropped before markup scanner takes it again
+            let scanner = resume_scanner(
+                &mut self.scanners.entry(uri.to_string()).or_default().code,
+                || syntax.scanner(),
+                |scanner| scanner.is_for(syntax),
+                rope,
+                offset,
+                Scanner::feed,
+            );
+            if scanner.finish() == Context::Code {
+                return false;
+            }
+        }
+        let (language_id, skip) = match markup_skip {
+            Some(markup_skip) => markup_skip,
+            None => return true,
+        };
+        match self.markup_context(uri, language_id, rope, offset) {
+            Some(Markup::Code) => !skip.code,
+            Some(Markup::Math) => !skip.math,
+            Some(Markup::FrontMatter) => !skip.front_matter,
+            _ => true,
+        }
+    }
+
+    /// what the offset of a markup document is in, scanning resumes from the cached line
+    fn markup_context(
+        &self,
+        uri: &Url,
+        language_id: &str,
+        rope: &Rope,
+        offset: usize,
+    ) -> Option<Markup> {
+        let fresh = scanner::markup_scanner(language_id)?;
+        let scanner = resume_scanner(
+            &mut self.scanners.entry(uri.to_string()).or_default().markup,
+            || fresh,
+            |scanner| scanner.is_for(language_id),
+            rope,
+            offset,
+            MarkupScanner::feed,
+        );
+        // inline math may be closed after the offset
+        let line_end = rope.line_to_char(rope.char_to_line(offset) + 1);
+        Some(scanner.finish(&Cow::from(rope.slice(offset..line_end))))
+    }
+
+    async fn get_option(&self, option: String) -> std::result::Result<bool, RimeError> {
+        // value of live sessions goes first
+        let name = option.clone();
+        let values = self
+            .with_all_states(move |state| state.session().get_option(&name))
+            .await?;
+        match values.into_iter().next() {
+            Some(value) => value,
+            None => {
+                let config = self.default_config().await;
+                let setup = self.session_setup(config, None);
+                worker::call(move || setup.create_session().get_option(&option)).await?
+            }
+        }
+    }
+
+    async fn set_option(&self, option: String, value: bool) -> std::result::Result<(), RimeError> {
+        // remember it for sessions created later
+        let mut config = self.config.write().await;
+        config.default_options.insert(option.clone(), value);
+        drop(config);
+        self.update_document_configs().await;
+        self.with_all_states(move |state| {
+            let res = state.session().set_option(&option, value);
+            // some options (e.g. ascii_mode) may commit current composition
+            state.reset();
+            res
+        })
+        .await?
+        .into_iter()
+        .collect()
+    }
+
+    async fn execute_option_command(
+        &self,
+        command: &str,
+        arguments: Vec<Value>,
+    ) -> Result<Option<Value>> {
+        let option = match arguments.first().and_then(Value::as_str) {
+            Some(option) => option.to_string(),
+            None => {
+                let msg = format!("{command} requires an option name as argument");
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(msg));
+            }
+        };
+        let res = match command {
+            "rime-ls.get-option" => self.get_option(option).await,
+            "rime-ls.set-option" => match arguments.get(1).and_then(Value::as_bool) {
+                Some(value) => self.set_option(option, value).await.map(|_| value),
+                None => {
+                    let msg = format!("{command} requires a bool value as second argument");
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(msg));
+                }
+            },
+            _ => match self.get_option(option.clone()).await {
+                Ok(value) => self.set_option(option, !value).await.map(|_| !value),
+                Err(e) => Err(e),
+            },
+        };
+        match res {
+            // return current value of the option
+            Ok(value) => Ok(Some(Value::from(value))),
+            Err(e) => {
+                self.client.log_message(MessageType::ERROR, &e).await;
+                Err(tower_lsp::jsonrpc::Error::internal_error())
+            }
+        }
+    }
+
+    async fn list_schemas(&self) -> Result<Option<Value>> {
+        let schemas = worker::call(|| Rime::global().get_schema_list())
+            .await
+            .map_err(|_| tower_lsp::jsonrpc::Error::internal_error())?;
+        Ok(serde_json::to_value(schemas).ok())
+    }
+
+    /// schema of a document if its uri is given, otherwise the one rime uses for new sessions
+    async fn get_current_schema(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let uri = match arguments
+            .into_iter()
+            .next()
+            .map(serde_json::from_value::<Url>)
         {
-            self.client.log_message(MessageType::WARNING, e).await;
-            return Err(tower_lsp::jsonrpc::Error::internal_error());
+            Some(Ok(uri)) => Some(uri),
+            Some(Err(e)) => return Err(tower_lsp::jsonrpc::Error::invalid_params(e.to_string())),
+            None => None,
+        };
+        let schema_id = self
+            .schema_of(uri.as_ref())
+            .await
+            .map_err(|_| tower_lsp::jsonrpc::Error::internal_error())?;
+        Ok(schema_id.map(Value::from))
+    }
+
+    /// tell rime which candidate is accepted, so that user dictionary learns it
+    async fn commit_candidate(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let data = arguments.into_iter().next().map(serde_json::from_value);
+        let data: CandidateData = match data {
+            Some(Ok(data)) => data,
+            _ => {
+                let msg = "rime-ls.commit-candidate requires candidate data as argument";
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(msg));
+            }
+        };
+        let slot = match self.state.get(&data.uri) {
+            Some(slot) => Arc::clone(&slot),
+            None => return Ok(Some(Value::from(false))),
+        };
+        let mut state = slot.lock_owned().await;
+        let (session, index, text) = (data.session, data.index, data.text);
+        let (input, offset) = (data.input, data.offset);
+        let committed = worker::call(move || match state.as_mut() {
+            // candidate is stale if session is replaced by a newer completion,
+            // or input is changed since the candidate is listed
+            Some(state)
+                if state.session().id() == session
+                    && state.input().raw_text() == input
+                    && state.offset() == offset =>
+            {
+                let res = state.session().commit_candidate(index, &text);
+                if let Ok(true) = res {
+                    // composition is committed or cleared
+                    state.reset();
+                }
+                res
+            }
+            _ => Ok(false),
+        })
+        .await
+        .and_then(|r| r);
+        match committed {
+            Ok(committed) => Ok(Some(Value::from(committed))),
+            Err(e) => {
+                self.client.log_message(MessageType::ERROR, &e).await;
+                Ok(Some(Value::from(false)))
+            }
         }
-        Ok(token)
     }
 
-    async fn notify_work_begin(&self, token: NumberOrString, message: &str) {
-        // begin
-        self.client
-            .send_notification::<notification::Progress>(ProgressParams {
-                token,
-                value: ProgressParamsValue::WorkDone(WorkDoneProgress::Begin(
-                    WorkDoneProgressBegin {
-                        title: message.to_string(),
-                        ..Default::default()
-                    },
-                )),
+    async fn select_schema(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let schema_id = match arguments.first().and_then(Value::as_str) {
+            Some(schema_id) => schema_id,
+            None => {
+                let msg = "rime-ls.select-schema requires a schema id as argument";
+                return Err(tower_lsp::jsonrpc::Error::invalid_params(msg));
+            }
+        };
+        let schema_id = schema_id.to_string();
+        let id = schema_id.clone();
+        let config = self.default_config().await;
+        let setup = self.session_setup(config, None);
+        let new_setup = setup.clone();
+        let results = self
+            .with_all_states(move |state| {
+                let res = setup.select_schema(state.session(), &id);
+                // composition is cleared by rime
+                state.reset();
+                res
             })
             .await;
+        let results = match results {
+            // no live session, select with a new one so that rime remembers it
+            Ok(v) if v.is_empty() => {
+                worker::call(move || {
+                    let session = Rime::global().create_session();
+                    vec![new_setup.select_schema(&session, &schema_id)]
+                })
+                .await
+            }
+            r => r,
+        };
+        let selected = match results {
+            Ok(v) => v.into_iter().all(|r| r.unwrap_or(false)),
+            Err(e) => {
+                self.client.log_message(MessageType::ERROR, &e).await;
+                false
+            }
+        };
+        // return a bool representing if schema is selected
+        Ok(Some(Value::from(selected)))
     }
 
-    async fn notify_work_done(&self, token: NumberOrString, message: &str) {
-        self.client
-            .send_notification::<notification::Progress>(ProgressParams {
-                token,
-                value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(WorkDoneProgressEnd {
-                    message: Some(message.to_string()),
-                })),
-            })
-            .await;
+    /// watch yaml files in user data dir, so that changes can be deployed automatically
+    async fn watch_user_data_dir(&self) {
+        let can_watch = self
+            .capabilities
+            .read()
+            .await
+            .workspace
+            .as_ref()
+            .and_then(|w| w.did_change_watched_files)
+            .and_then(|c| c.dynamic_registration)
+            .unwrap_or(false);
+        if !can_watch {
+            let info = "Client can not watch files, auto deploy is disabled.";
+            self.client.log_message(MessageType::INFO, info).await;
+            return;
+        }
+        let user_data_dir = utils::expand_tilde(&self.config.read().await.user_data_dir);
+        let pattern = format!("{}/**/*.yaml", utils::path_to_slash(&user_data_dir));
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String(pattern),
+                kind: None,
+            }],
+        };
+        let registration = Registration {
+            id: WATCH_USER_DATA_DIR.to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(options).ok(),
+        };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            self.client.log_message(MessageType::WARNING, e).await;
+        }
+    }
+
+    async fn unwatch_user_data_dir(&self) {
+        let unregistration = Unregistration {
+            id: WATCH_USER_DATA_DIR.to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+        };
+        if let Err(e) = self
+            .client
+            .unregister_capability(vec![unregistration])
+            .await
+        {
+            self.client.log_message(MessageType::INFO, e).await;
+        }
+    }
+
+    /// deploy in background after file changes settle down
+    fn schedule_deploy(&self) {
+        let request = self.deploy_requests.fetch_add(1, Ordering::SeqCst) + 1;
+        let requests = self.deploy_requests.clone();
+        let client = self.client.clone();
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(DEPLOY_DEBOUNCE_MS)).await;
+            // a newer change arrives, leave it to deploy
+            if requests.load(Ordering::SeqCst) != request {
+                return;
+            }
+            match deploy_rime(&state).await {
+                Ok(_) => {
+                    let info = "Rime config changed, redeployed.";
+                    client.show_message(MessageType::INFO, info).await;
+                }
+                Err(e) => {
+                    client.log_message(MessageType::ERROR, &e).await;
+                    client.show_message(MessageType::ERROR, e).await;
+                }
+            }
+        });
     }
 
     async fn get_completions(&self, uri: Url, position: Position) -> Option<CompletionList> {
         // get new input
-        let rope = self.documents.get(uri.as_str())?;
+        // clone the rope (cheap) so no map guard is held across awaits
+        let rope = self.documents.get(uri.as_str())?.clone();
         let encoding = *self.encoding.read().await;
         let line_begin = {
             let line_pos = Position::new(position.line, 0);
             utils::position_to_offset(&rope, line_pos, encoding)?
         };
         let curr_char = utils::position_to_offset(&rope, position, encoding)?;
+        let (config, re) = self.document_config(&uri).await;
         let new_input = {
-            let re = self.regex.read().await;
-            let has_trigger = !self.config.read().await.trigger_characters.is_empty();
-            let schema_trigger = &self.config.read().await.schema_trigger_character;
+            let has_trigger = !config.trigger_characters.is_empty();
+            let schema_trigger = &config.schema_trigger_character;
             (curr_char <= rope.len_chars()).then(|| {
                 let slice = Cow::from(rope.slice(line_begin..curr_char));
                 if utils::need_to_check_trigger(has_trigger, &slice) {
@@ -161,19 +1084,46 @@ impl Backend {
                 }
             })??
         };
+        // scanning is slower than matching input, so it goes after
+        if !self.in_completion_scope(&config, &uri, &rope, curr_char) {
+            return None;
+        }
         let new_offset = curr_char - new_input.raw_text().len();
 
-        // handle new input
-        let mut last_state = self.state.entry(uri.into()).or_default();
-        let InputResult {
-            session_id,
-            extra_offset,
-        } = match (*last_state).as_ref() {
-            Some(state) => {
-                let max_tokens = self.config.read().await.max_tokens;
-                state.apply_input(new_offset, &new_input, max_tokens)
+        // handle new input on rime thread, one completion at a time for a document
+        let slot = Arc::clone(&self.state.entry(uri.to_string()).or_default());
+        let mut state = slot.lock_owned().await;
+        let (max_tokens, max_candidates, candidate_source) = (
+            config.max_tokens,
+            config.max_candidates,
+            config.candidate_source,
+        );
+        let setup = self.session_setup(Arc::clone(&config), Some(&uri));
+        let handled = worker::call(move || {
+            let InputResult {
+                session,
+                extra_offset,
+            } = match state.take() {
+                Some(last) => last.apply_input(new_offset, &new_input, max_tokens, &setup),
+                None => InputState::first_input(&new_input, &setup),
+            };
+            let response = session.get_response().and_then(|mut r| {
+                // replace current page with candidates of all pages when composing
+                if candidate_source == CandidateSource::All && r.is_incomplete {
+                    r.candidates = session.get_all_candidates(max_candidates)?;
+                }
+                Ok(r)
+            });
+            (state, new_input, session, extra_offset, response)
+        })
+        .await;
+        let (mut state, new_input, session, extra_offset, response) = match handled {
+            Ok(r) => r,
+            Err(e) => {
+                self.client.log_message(MessageType::ERROR, &e).await;
+                self.client.show_message(MessageType::ERROR, e).await;
+                None?
             }
-            None => InputState::first_input(&new_input),
         };
 
         // NOTE: prevent deleting puncts before real pinyin input
@@ -182,21 +1132,19 @@ impl Backend {
 
         let start_position = utils::offset_to_position(&rope, real_offset, encoding)?;
         let range = Range::new(start_position, position);
-        let filter_prefix = (self.config.read().await.long_filter_text).then_some({
+        let filter_prefix = config.long_filter_text.then_some({
             let slice = &Cow::from(rope.slice(line_begin..real_offset));
             utils::surrounding_word(slice).to_string()
         });
-        // TODO: Does compiler know the right time to drop the lock,
-        // or it will wait until the end of this function?
-        drop(rope);
 
         // get candidates from current session
-        let rime = Rime::global();
         let RimeResponse {
             is_incomplete,
             submitted,
+            preedit,
             candidates,
-        } = match rime.get_response_from_session(session_id) {
+            menu,
+        } = match response {
             Ok(r) => r,
             Err(e) => {
                 self.client.log_message(MessageType::ERROR, &e).await;
@@ -209,33 +1157,74 @@ impl Backend {
         let filter_text = filter_prefix.unwrap_or_default() + new_input.raw_text();
 
         // update input state
-        *last_state = Some(InputState::new(
+        let (uri_str, session_id) = (uri.to_string(), session.id());
+        let raw_text = new_input.raw_text().to_owned();
+        *state = Some(InputState::new(
             new_input,
-            session_id,
+            session,
             new_offset,
             is_incomplete,
         ));
-        drop(last_state);
+        drop(state);
 
         // convert candidates to completions
-        let (show_filter_text_in_label, show_order_in_label, preselect_enabled, max_candidates) = {
-            let config = self.config.read().await;
-            (
-                config.show_filter_text_in_label,
-                config.show_order_in_label,
-                config.preselect_first,
-                config.max_candidates,
-            )
-        };
+        let (show_filter_text_in_label, show_order_in_label, preselect_enabled) = (
+            config.show_filter_text_in_label,
+            config.show_order_in_label,
+            config.preselect_first,
+        );
         let order_to_sort_text = utils::build_order_to_sort_text(max_candidates);
+        // candidates of all pages are indexed from the first page
+        let (highlighted_index, page_size) = match candidate_source {
+            CandidateSource::Page => (menu.highlighted_index, None),
+            CandidateSource::All => (
+                menu.page_no * menu.page_size + menu.highlighted_index,
+                Some(menu.page_size.max(1)),
+            ),
+        };
+        // only show page number when there are more than one page
+        let has_pages = menu.page_no > 0 || !menu.is_last_page;
+        let page_no_of = |i: usize| page_size.map_or(menu.page_no, |size| i / size);
+        let page_info = |i: usize| has_pages.then(|| format!("page {}", page_no_of(i) + 1));
+        // digits select in current page of rime, so orders in other pages are hidden
+        let select_order = |i: usize, order: usize| match page_size {
+            Some(size) => (page_no_of(i) == menu.page_no).then_some(i % size + 1),
+            None => Some(order),
+        };
+        // tell rime the accepted candidate, see `Backend::commit_candidate`
+        let composing = is_incomplete;
+        let commit_command = |i: usize, text: &str| {
+            let index = match candidate_source {
+                CandidateSource::Page => menu.page_no * menu.page_size + i,
+                CandidateSource::All => i,
+            };
+            let data = CandidateData {
+                uri: uri_str.clone(),
+                session: session_id,
+                index,
+                text: text.to_owned(),
+                input: raw_text.clone(),
+                offset: new_offset,
+                preedit: preedit.clone(),
+            };
+            let data = serde_json::to_value(data).ok();
+            // commit text is not a candidate of rime
+            let command = data.clone().filter(|_| composing).map(|data| Command {
+                title: "commit candidate".to_owned(),
+                command: "rime-ls.commit-candidate".to_owned(),
+                arguments: Some(vec![data]),
+            });
+            (data, command)
+        };
         let candidate_to_completion_item = |(i, c): (usize, Candidate)| -> CompletionItem {
+            let (data, command) = commit_command(i, &c.text);
             let text = match is_selecting {
                 true => submitted.clone() + &c.text,
                 false => c.text,
             };
-            let mut label = match c.order {
-                0 => text.clone(),
-                _ if show_order_in_label => format!("{}. {}", c.order, &text),
+            let mut label = match select_order(i, c.order) {
+                _ if c.order == 0 => text.clone(),
+                Some(order) if show_order_in_label => format!("{}. {}", order, &text),
                 _ => text.clone(),
             };
             if show_filter_text_in_label {
@@ -247,21 +1236,29 @@ impl Backend {
                 detail: Some(c.comment.clone()),
                 description: None,
             });
+            let detail = match page_info(i) {
+                Some(page) if c.comment.is_empty() => Some(page),
+                Some(page) => Some(format!("{} ({})", c.comment, page)),
+                None => utils::option_string(c.comment),
+            };
             CompletionItem {
                 label,
                 label_details,
-                preselect: (preselect_enabled && i == 0).then_some(true),
+                preselect: (preselect_enabled && i == highlighted_index).then_some(true),
                 kind: Some(CompletionItemKind::TEXT),
-                detail: utils::option_string(c.comment),
+                detail,
                 filter_text: Some(filter_text.clone()),
                 sort_text: Some(order_to_sort_text(c.order)),
                 text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, text))),
+                command,
+                data,
                 ..Default::default()
             }
         };
 
         // return completions
-        let is_incomplete = self.config.read().await.always_incomplete || is_incomplete;
+        // more pages means the list should be requested again when paging
+        let is_incomplete = config.always_incomplete || is_incomplete || !menu.is_last_page;
         let item_iter = candidates
             .into_iter()
             .enumerate()
@@ -274,17 +1271,27 @@ impl Backend {
     }
 }
 
+impl Drop for Backend {
+    fn drop(&mut self) {
+        CLIENTS.remove(&self.id);
+    }
+}
+
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
     async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        // remember who is connected
+        if let (Some(info), Some(mut client)) = (&params.client_info, CLIENTS.get_mut(&self.id)) {
+            client.name = Some(info.name.clone());
+            client.version = info.version.clone();
+        }
         // read user configuration
-        if let Some(init_options) = params.initialization_options {
-            self.init_config(init_options).await;
-        } else {
+        if params.initialization_options.is_none() {
             self.client
                 .log_message(MessageType::INFO, "Use default config")
                 .await;
         }
+        self.init_config(params.initialization_options).await;
         // init rime
         if let Err(e) = self.init().await {
             self.client.log_message(MessageType::ERROR, &e).await;
@@ -297,11 +1304,36 @@ impl LanguageServer for Backend {
             .await;
         // set LSP triggers
         let triggers = {
-            let mut triggers = self.config.read().await.paging_characters.clone(); // for paging
-            let user_triggers = &self.config.read().await.trigger_characters;
-            triggers.extend_from_slice(user_triggers);
+            let config = self.config.read().await;
+            let mut triggers = config.paging_characters.clone(); // for paging
+            triggers.extend_from_slice(&config.trigger_characters);
+            // triggers of workspace folders
+            let folder_triggers = config
+                .workspace_settings
+                .values()
+                .filter_map(|settings| settings.trigger_characters.as_ref())
+                .flatten();
+            for c in folder_triggers {
+                if !triggers.contains(c) {
+                    triggers.push(c.clone());
+                }
+            }
             triggers
         };
+        // remember workspace folders
+        #[allow(deprecated)]
+        let folders = params.workspace_folders.clone().or_else(|| {
+            let uri = params.root_uri.clone()?;
+            let name = uri
+                .path_segments()?
+                .rev()
+                .find(|s| !s.is_empty())?
+                .to_string();
+            Some(vec![WorkspaceFolder { uri, name }])
+        });
+        *self.workspace_folders.write().await = folders.unwrap_or_default();
+        self.update_document_configs().await;
+        *self.capabilities.write().await = params.capabilities.clone();
         // negotiate position encoding
         let encoding_options = params
             .capabilities
@@ -328,8 +1360,9 @@ impl LanguageServer for Backend {
                     }),
                     file_operations: None,
                 }),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
                 completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(false),
+                    resolve_provider: Some(true),
                     trigger_characters: Some(triggers),
                     ..CompletionOptions::default()
                 }),
@@ -337,6 +1370,17 @@ impl LanguageServer for Backend {
                     commands: vec![
                         "rime-ls.toggle-rime".to_string(),
                         "rime-ls.sync-user-data".to_string(),
+                        "rime-ls.deploy".to_string(),
+                        "rime-ls.server-info".to_string(),
+                        "rime-ls.show-config".to_string(),
+                        "rime-ls.list-schemas".to_string(),
+                        "rime-ls.get-current-schema".to_string(),
+                        "rime-ls.select-schema".to_string(),
+                        "rime-ls.get-option".to_string(),
+                        "rime-ls.set-option".to_string(),
+                        "rime-ls.toggle-option".to_string(),
+                        "rime-ls.commit-candidate".to_string(),
+                        "rime-ls.reverse-lookup".to_string(),
                     ],
                     work_done_progress_options: WorkDoneProgressOptions {
                         work_done_progress: Some(true),
@@ -347,20 +1391,44 @@ impl LanguageServer for Backend {
         })
     }
 
+    async fn initialized(&self, _: InitializedParams) {
+        // settings may change data dirs, so pull them before maintenance
+        self.pull_settings().await;
+        // maintain rime in background, so that following notifications are not blocked
+        if Rime::is_initialized() && !Rime::global().is_ready() {
+            let client = self.client.clone();
+            tokio::spawn(async move {
+                let command = "rime-ls.maintenance";
+                let token = NumberOrString::String(command.to_string());
+                let token = create_work_done_progress(&client, token).await.ok();
+                if let Some(token) = token.clone() {
+                    notify_work_begin(&client, token, "Rime is deploying").await;
+                }
+                if let Err(e) = worker::call(|| Rime::global().maintain()).await {
+                    client.log_message(MessageType::ERROR, &e).await;
+                }
+                if let Some(token) = token {
+                    notify_work_done(&client, token, "Rime is Ready.").await;
+                }
+            });
+        }
+        if self.config.read().await.auto_deploy {
+            self.watch_user_data_dir().await;
+        }
+    }
+
     async fn shutdown(&self) -> Result<()> {
         // destroy rime sessions on server shutdown
-        let rime = Rime::global();
-        for kvref in self.state.iter() {
-            if let Some(state) = kvref.value().as_ref() {
-                rime.destroy_session(state.session_id());
-            }
-        }
+        self.state.clear();
         Ok(())
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        let url = params.text_document.uri.into();
+        let url: String = params.text_document.uri.into();
         let rope = Rope::from(params.text_document.text);
+        self.language_ids
+            .insert(url.clone(), params.text_document.language_id);
+        self.scanners.remove(&url);
         self.documents.insert(url, rope);
     }
 
@@ -378,11 +1446,16 @@ impl LanguageServer for Backend {
                         if let (Some(s), Some(e)) = (s, e) {
                             rope.remove(s..e);
                             rope.insert(s, &text);
+                            // scanner is stale if text before it is changed
+                            if let Some(mut scanners) = self.scanners.get_mut(url.as_str()) {
+                                scanners.changed_at(s);
+                            }
                         }
                     }
                     // full content change
                     None => {
                         *rope = Rope::from(text);
+                        self.scanners.remove(url.as_str());
                     }
                 }
             }
@@ -393,18 +1466,61 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "settings changed")
             .await;
-        self.apply_settings(params.settings).await;
+        // some clients push settings, others send nothing and wait for a pull
+        let pushed = params.settings.as_object().is_some_and(|s| !s.is_empty());
+        if pushed {
+            self.apply_settings(params.settings).await;
+        } else {
+            self.pull_settings().await;
+        }
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let (auto_deploy, user_data_dir) = {
+            let config = self.config.read().await;
+            (
+                config.auto_deploy,
+                utils::expand_tilde(&config.user_data_dir),
+            )
+        };
+        let need_deploy = params
+            .changes
+            .iter()
+            .filter_map(|event| event.uri.to_file_path().ok())
+            .any(|path| utils::need_deploy(&user_data_dir, &path));
+        if auto_deploy && need_deploy {
+            self.schedule_deploy();
+        }
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let mut folders = self.workspace_folders.write().await;
+        folders.retain(|f| !params.event.removed.iter().any(|r| r.uri == f.uri));
+        folders.extend(params.event.added);
+        drop(folders);
+        self.update_document_configs().await;
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         let uri = params.text_document.uri.as_str();
         self.documents.remove(uri);
+        self.language_ids.remove(uri);
+        self.scanners.remove(uri);
+        // drop input state and its rime session
+        self.state.remove(uri);
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
         if !self.config.read().await.enabled {
             return Ok(None);
         }
+        if !Rime::is_initialized() || !Rime::global().is_ready() {
+            // rime is deploying, let client request again later
+            return Ok(Some(CompletionResponse::List(CompletionList {
+                is_incomplete: true,
+                items: Vec::new(),
+            })));
+        }
         let uri = params.text_document_position.text_document.uri;
         let position = params.text_document_position.position;
 
@@ -412,40 +1528,143 @@ impl LanguageServer for Backend {
         Ok(completions.map(CompletionResponse::List))
     }
 
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        if !Rime::is_initialized() || !Rime::global().is_ready() {
+            return Ok(None);
+        }
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        let encoding = *self.encoding.read().await;
+        let (chars, begin, at) = match self.han_around(&uri, position, encoding) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let (value, word) = match self.readings_of(&uri, chars, at).await {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let range = self.documents.get(uri.as_str()).and_then(|rope| {
+            let start = utils::offset_to_position(&rope, begin + word.start, encoding)?;
+            let end = utils::offset_to_position(&rope, begin + word.end, encoding)?;
+            Some(Range::new(start, end))
+        });
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range,
+        }))
+    }
+
+    async fn completion_resolve(&self, mut item: CompletionItem) -> Result<CompletionItem> {
+        let data = item
+            .data
+            .clone()
+            .map(serde_json::from_value::<CandidateData>);
+        let data = match data {
+            Some(Ok(data)) => data,
+            _ => return Ok(item),
+        };
+        let mut lines = Vec::new();
+        if !data.preedit.is_empty() {
+            lines.push(format!("preedit: `{}`", data.preedit));
+        }
+        lines.push(format!("input: `{}`", data.input));
+        if let Some(comment) = item.label_details.as_ref().and_then(|d| d.detail.as_ref()) {
+            lines.push(format!("comment: `{comment}`"));
+        }
+        // codes of a single character help learning shape-based schemas
+        let mut chars = data.text.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            lines.push(format!("code point: `U+{:04X}`", c as u32));
+            let uri = Url::parse(&data.uri).ok();
+            let codes = match self.schema_dictionary(uri.as_ref()).await {
+                Some(dictionary) => self.lookup_codes(dictionary, vec![data.text]).await,
+                None => Vec::new(),
+            };
+            if let Some(codes) = codes.first() {
+                if !codes.is_empty() {
+                    let codes: Vec<String> = codes.iter().map(|c| format!("`{c}`")).collect();
+                    lines.push(format!("codes: {}", codes.join(", ")));
+                }
+            }
+        }
+        item.documentation = Some(Documentation::MarkupContent(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: lines.join("  \n"),
+        }));
+        Ok(item)
+    }
+
     async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
         let command: &str = params.command.as_ref();
+        // queries answer at once, no progress is needed
+        match command {
+            // sent on every accepted completion
+            "rime-ls.commit-candidate" => return self.commit_candidate(params.arguments).await,
+            "rime-ls.server-info" => return Ok(Some(self.server_info())),
+            "rime-ls.show-config" => {
+                let config = self.config.read().await;
+                return Ok(serde_json::to_value(&*config).ok());
+            }
+            "rime-ls.list-schemas" => return self.list_schemas().await,
+            "rime-ls.get-current-schema" => return self.get_current_schema(params.arguments).await,
+            "rime-ls.select-schema" => return self.select_schema(params.arguments).await,
+            "rime-ls.reverse-lookup" => return self.reverse_lookup(params.arguments).await,
+            "rime-ls.get-option" | "rime-ls.set-option" | "rime-ls.toggle-option" => {
+                return self.execute_option_command(command, params.arguments).await;
+            }
+            "rime-ls.toggle-rime" | "rime-ls.sync-user-data" | "rime-ls.deploy" => (),
+            _ => {
+                self.client
+                    .log_message(MessageType::WARNING, "No such rime-ls command")
+                    .await;
+                return Ok(None);
+            }
+        }
         let token = {
             match params.work_done_progress_params.work_done_token {
                 Some(token) => token,
                 None => {
                     let token = NumberOrString::String(command.to_string());
-                    self.create_work_done_progress(token).await?
+                    create_work_done_progress(&self.client, token).await?
                 }
             }
         };
         match command {
             "rime-ls.toggle-rime" => {
-                self.notify_work_begin(token.clone(), command).await;
+                notify_work_begin(&self.client, token.clone(), command).await;
                 let mut config = self.config.write().await;
                 config.enabled = !config.enabled;
                 let status = match config.enabled {
                     true => "Rime is ON",
                     false => "Rime is OFF",
                 };
-                self.notify_work_done(token.clone(), status).await;
+                notify_work_done(&self.client, token.clone(), status).await;
                 // return a bool representing if rime-ls is enabled
                 return Ok(Some(Value::from(config.enabled)));
             }
             "rime-ls.sync-user-data" => {
-                self.notify_work_begin(token.clone(), command).await;
-                Rime::global().sync_user_data();
-                self.notify_work_done(token.clone(), "Rime is Ready.").await;
+                notify_work_begin(&self.client, token.clone(), command).await;
+                if let Err(e) = worker::call(|| Rime::global().sync_user_data()).await {
+                    self.client.log_message(MessageType::ERROR, &e).await;
+                }
+                notify_work_done(&self.client, token.clone(), "Rime is Ready.").await;
             }
-            _ => {
-                self.client
-                    .log_message(MessageType::WARNING, "No such rime-ls command")
-                    .await;
+            "rime-ls.deploy" => {
+                notify_work_begin(&self.client, token.clone(), command).await;
+                let status = match deploy_rime(&self.state).await {
+                    Ok(_) => "Rime is Ready.",
+                    Err(e) => {
+                        self.client.log_message(MessageType::ERROR, &e).await;
+                        self.client.show_message(MessageType::ERROR, e).await;
+                        "Rime deployment failed."
+                    }
+                };
+                notify_work_done(&self.client, token.clone(), status).await;
             }
+            _ => unreachable!(),
         }
         Ok(None)
     }
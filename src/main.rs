@@ -1,59 +1,318 @@
-use rime_ls::lsp::Backend;
+use clap::Parser;
+use rime_ls::config::{self, LogLevel};
+use rime_ls::lsp::{self, Backend, ServerOptions};
 use rime_ls::rime::Rime;
-use std::{net::SocketAddr, str::FromStr};
+use rime_ls::worker;
+use serde_json::{json, Map, Value};
+use std::{net::SocketAddr, path::Path, sync::Arc, time::Duration};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::broadcast::{self, Receiver};
 use tower_lsp::{LspService, Server};
 
-async fn run_stdio() {
+#[cfg(feature = "tls")]
+type TlsAcceptor = tokio_rustls::TlsAcceptor;
+#[cfg(not(feature = "tls"))]
+type TlsAcceptor = ();
+
+/// options of TCP mode
+#[derive(Clone)]
+struct TcpOptions {
+    /// options of every connection
+    server: Arc<ServerOptions>,
+    /// accept TLS connections only
+    #[cfg_attr(not(feature = "tls"), allow(dead_code))]
+    tls: Option<TlsAcceptor>,
+}
+
+async fn run_stdio(options: Arc<ServerOptions>) {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::build(Backend::new).finish();
+    let (service, socket) =
+        LspService::build(|client| Backend::with_options(client, options)).finish();
     Server::new(stdin, stdout, socket).serve(service).await;
 }
 
-async fn run_tcp(stream: TcpStream) {
-    let (read, write) = tokio::io::split(stream);
+/// max length of headers and body of the first message
+const MAX_HEADER_LEN: usize = 1024;
+const MAX_INITIALIZE_LEN: usize = 1 << 20;
+/// time for a client to send the first message, the connection is closed after it
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// read a whole message, raw bytes are returned too so that they can be replayed
+async fn read_message<R>(read: &mut R) -> std::io::Result<(Vec<u8>, Value)>
+where
+    R: AsyncRead + Unpin,
+{
+    use std::io::{Error, ErrorKind};
+    let mut raw = Vec::new();
+    // headers end with an empty line
+    while !raw.ends_with(b"\r\n\r\n") {
+        if raw.len() > MAX_HEADER_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "headers are too long"));
+        }
+        raw.push(read.read_u8().await?);
+    }
+    let length = String::from_utf8_lossy(&raw)
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok());
+    let length = match length {
+        Some(length) if length <= MAX_INITIALIZE_LEN => length,
+        _ => return Err(Error::new(ErrorKind::InvalidData, "bad Content-Length")),
+    };
+    let start = raw.len();
+    raw.resize(start + length, 0);
+    read.read_exact(&mut raw[start..]).await?;
+    let message = serde_json::from_slice(&raw[start..])?;
+    Ok((raw, message))
+}
+
+async fn serve<S>(stream: S, options: Arc<ServerOptions>)
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (mut read, mut write) = tokio::io::split(stream);
+    // check token before serving, so that a bad client can not retry on the same connection
+    let mut first = Vec::new();
+    if let Some(token) = &options.token {
+        let message = match tokio::time::timeout(AUTH_TIMEOUT, read_message(&mut read)).await {
+            Ok(Ok((raw, message))) if lsp::authenticate(token, &message) => {
+                first = raw;
+                message
+            }
+            Ok(Ok((_, message))) => message,
+            Ok(Err(e)) => {
+                eprintln!("Failed to read initialize request: {e}");
+                return;
+            }
+            Err(_) => {
+                eprintln!("No initialize request in time, connection closed");
+                let _ = write.shutdown().await;
+                return;
+            }
+        };
+        if first.is_empty() {
+            eprintln!("Invalid token, connection rejected");
+            let body = json!({
+                "jsonrpc": "2.0",
+                "id": message.get("id"),
+                "error": { "code": -32602, "message": "Invalid token, connection rejected" },
+            })
+            .to_string();
+            let response = format!("Content-Length: {}\r\n\r\n{body}", body.len());
+            let _ = write.write_all(response.as_bytes()).await;
+            let _ = write.shutdown().await;
+            return;
+        }
+    }
+    // replay the first message to server
+    let read = std::io::Cursor::new(first).chain(read);
 
-    let (service, socket) = LspService::build(Backend::new).finish();
+    let (service, socket) =
+        LspService::build(|client| Backend::with_options(client, options)).finish();
     Server::new(read, write, socket).serve(service).await;
 }
 
-async fn run_tcp_forever(bind_addr: SocketAddr) -> tokio::io::Result<()> {
+async fn run_tcp(stream: TcpStream, options: TcpOptions) {
+    #[cfg(feature = "tls")]
+    if let Some(acceptor) = options.tls {
+        match acceptor.accept(stream).await {
+            Ok(stream) => serve(stream, options.server).await,
+            Err(e) => eprintln!("TLS handshake failed: {e}"),
+        }
+        return;
+    }
+    serve(stream, options.server).await;
+}
+
+async fn run_tcp_forever(bind_addr: SocketAddr, options: TcpOptions) -> tokio::io::Result<()> {
     println!("Listening on: {}", &bind_addr);
     let listener = TcpListener::bind(bind_addr).await?;
     loop {
         let (stream, _) = listener.accept().await?;
-        tokio::spawn(run_tcp(stream));
+        tokio::spawn(run_tcp(stream, options.clone()));
     }
 }
 
-async fn run(mut shutdown: Receiver<()>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut args = std::env::args();
-    match args.nth(1).as_deref() {
-        None => {
-            tokio::select! {
-                _ = shutdown.recv() => (),
-                _ = run_stdio() => ()
-            }
+#[cfg(unix)]
+async fn run_unix_forever(path: &str, options: Arc<ServerOptions>) -> tokio::io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use tokio::net::{UnixListener, UnixStream};
+
+    // remove stale socket left by a crashed server, but never steal a live one
+    if std::path::Path::new(path).exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            let msg = format!("{path} is used by another rime_ls");
+            return Err(std::io::Error::new(std::io::ErrorKind::AddrInUse, msg));
         }
-        Some("--listen") => {
-            let addr = args.next().unwrap_or("127.0.0.1:9257".to_owned());
-            let addr = SocketAddr::from_str(&addr)?;
-            tokio::select! {
-                _ = shutdown.recv() => Ok(()),
-                Err(e) = run_tcp_forever(addr) => Err(e),
-            }?
+        std::fs::remove_file(path)?;
+    }
+    // bind in a private dir and move socket out after only current user can connect,
+    // so that nobody else can connect before its permissions are set
+    let parent = Path::new(path)
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let private_dir = parent.join(format!(".rime-ls-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&private_dir);
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)?;
+    let temp_path = private_dir.join("socket");
+    let listener = UnixListener::bind(&temp_path).and_then(|listener| {
+        std::fs::set_permissions(&temp_path, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_dir_all(&private_dir);
+    let listener = listener?;
+    println!("Listening on: {path}");
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(serve(stream, Arc::clone(&options)));
+    }
+}
+
+#[cfg(not(unix))]
+async fn run_unix_forever(_path: &str, _options: Arc<ServerOptions>) -> tokio::io::Result<()> {
+    let msg = "unix domain socket is not supported on this platform";
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, msg))
+}
+
+#[cfg(feature = "tls")]
+fn load_tls(cert: &str, key: &str) -> std::io::Result<TlsAcceptor> {
+    use std::fs::File;
+    use std::io::{BufReader, Error, ErrorKind};
+    use tokio_rustls::rustls::ServerConfig;
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))?
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no private key found"))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[cfg(not(feature = "tls"))]
+fn load_tls(_cert: &str, _key: &str) -> std::io::Result<TlsAcceptor> {
+    let msg = "rime_ls is built without feature `tls`";
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, msg))
+}
+
+/// A language server for Rime input method engine
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Communicate with client via stdin/stdout, this is the default mode
+    #[arg(long, conflicts_with_all = ["listen", "socket"])]
+    stdio: bool,
+    /// Listen on TCP address, `127.0.0.1:9257` if address is omitted
+    #[arg(long, value_name = "BIND_ADDR", num_args = 0..=1, default_missing_value = "127.0.0.1:9257")]
+    listen: Option<SocketAddr>,
+    /// Token that clients must present in initializationOptions (TCP mode)
+    #[arg(long, env = "RIME_LS_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// PEM certificate chain to serve TCP connections with TLS
+    #[arg(long, value_name = "PATH", requires_all = ["listen", "tls_key"])]
+    tls_cert: Option<String>,
+    /// PEM private key of the certificate
+    #[arg(long, value_name = "PATH", requires_all = ["listen", "tls_cert"])]
+    tls_key: Option<String>,
+    /// Listen on Unix domain socket, only current user can connect
+    #[arg(long, value_name = "PATH", conflicts_with = "listen")]
+    socket: Option<String>,
+    /// Default rime shared data dir
+    #[arg(long, value_name = "DIR")]
+    shared_data_dir: Option<String>,
+    /// Default rime user data dir
+    #[arg(long, value_name = "DIR")]
+    user_data_dir: Option<String>,
+    /// Default rime log dir
+    #[arg(long, value_name = "DIR")]
+    log_dir: Option<String>,
+    /// Default minimal level of rime log
+    #[arg(long, value_enum)]
+    log_level: Option<LogLevel>,
+    /// Config file in toml or json, `rime-ls.toml` or `rime-ls.json` in user config dir by default
+    #[arg(long, value_name = "FILE")]
+    config: Option<String>,
+}
+
+impl Cli {
+    /// default config built from config file and command line flags
+    fn server_options(&self) -> Result<ServerOptions, Box<dyn std::error::Error>> {
+        let (mut defaults, config_warnings) =
+            match config::read_config_file(self.config.as_deref().map(Path::new)) {
+                Ok(defaults) => (defaults, Vec::new()),
+                // only a config file given by `--config` must be valid, or the server can not start
+                Err(e) if self.config.is_none() => {
+                    eprintln!("{e}");
+                    (Map::new(), vec![e])
+                }
+                Err(e) => return Err(e.into()),
+            };
+        // flags go after config file
+        let flags = [
+            (
+                "shared_data_dir",
+                self.shared_data_dir.clone().map(Value::from),
+            ),
+            ("user_data_dir", self.user_data_dir.clone().map(Value::from)),
+            ("log_dir", self.log_dir.clone().map(Value::from)),
+            ("log_level", self.log_level.map(|l| json!(l))),
+        ];
+        for (key, value) in flags {
+            if let Some(value) = value {
+                defaults.insert(key.to_owned(), value);
+            }
         }
-        _ => usage(),
+        Ok(ServerOptions {
+            defaults,
+            config_warnings,
+            ..Default::default()
+        })
     }
-    Ok(())
 }
 
-fn usage() {
-    println!("rime_ls v{}", env!("CARGO_PKG_VERSION"));
-    println!("Usage: rime_ls [--listen <bind_addr>]")
+async fn run(cli: Cli, mut shutdown: Receiver<()>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut options = cli.server_options()?;
+    if let Some(addr) = cli.listen {
+        let tls = match (&cli.tls_cert, &cli.tls_key) {
+            (Some(cert), Some(key)) => Some(load_tls(cert, key)?),
+            _ => None,
+        };
+        options.token = cli.token;
+        let options = TcpOptions {
+            server: Arc::new(options),
+            tls,
+        };
+        tokio::select! {
+            _ = shutdown.recv() => Ok(()),
+            Err(e) = run_tcp_forever(addr, options) => Err(e),
+        }?
+    } else if let Some(path) = cli.socket {
+        let res = tokio::select! {
+            _ = shutdown.recv() => Ok(()),
+            Err(e) = run_unix_forever(&path, Arc::new(options)) => Err(e),
+        };
+        // socket file is useless after server exits
+        if res.is_ok() {
+            let _ = std::fs::remove_file(&path);
+        }
+        res?
+    } else {
+        tokio::select! {
+            _ = shutdown.recv() => (),
+            _ = run_stdio(Arc::new(options)) => ()
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
@@ -67,11 +326,14 @@ async fn main() {
         tx.send(()).unwrap();
     });
     // run
-    if let Err(e) = run(rx).await {
+    if let Err(e) = run(Cli::parse(), rx).await {
         eprintln!("{e}");
     }
     // finalize rime if necessary
-    if Rime::is_initialized() {
-        Rime::global().destroy();
-    }
+    let _ = worker::call(|| {
+        if Rime::is_initialized() {
+            Rime::global().destroy();
+        }
+    })
+    .await;
 }
@@ -1,7 +1,10 @@
-use crate::consts::{APP_NAME, KEY_BACKSPACE, KEY_ESCAPE, RAW_RE};
+use crate::consts::{APP_NAME, KEY_BACKSPACE, KEY_ESCAPE, RAW_RE, SCHEMA_ID_MAX_LEN};
+use crate::worker;
 use librime_sys as librime;
 use once_cell::sync::OnceCell;
+use serde::Serialize;
 use std::ffi::{c_char, CStr, CString, NulError};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Mutex;
 use thiserror::Error;
 
@@ -26,13 +29,35 @@ macro_rules! rime_call {
     };
 }
 
+/// copy a nullable C string into an owned String
+fn to_owned_string(ptr: *const c_char) -> Option<String> {
+    (!ptr.is_null())
+        .then(|| unsafe { CStr::from_ptr(ptr) }.to_str().ok())
+        .flatten()
+        .map(|s| s.to_owned())
+}
+
 /// global rime instance
 static RIME: OnceCell<Rime> = OnceCell::new();
 
-/// just call unsafe c ffi function simply
-/// TODO: make a good rust wrapper
+/// the initialized librime and its data dirs, sessions are owned by `RimeSession`
+///
+/// all calls to librime are made on the rime thread, see `worker`
 #[derive(Debug)]
-pub struct Rime;
+pub struct Rime {
+    /// data dirs can be switched at runtime
+    shared_data_dir: Mutex<String>,
+    user_data_dir: Mutex<String>,
+    /// logging can only be set up once in a process, so log dir is fixed
+    log_dir: String,
+    /// minimal level of glog, 0-3 for INFO, WARNING, ERROR, FATAL
+    #[cfg_attr(feature = "no_log_dir", allow(dead_code))]
+    min_log_level: i32,
+    /// increased when librime is re-initialized, sessions created before are invalid
+    generation: AtomicUsize,
+    /// false until maintenance is done
+    ready: AtomicBool,
+}
 
 #[derive(Debug)]
 pub struct Candidate {
@@ -65,6 +90,28 @@ pub enum RimeError {
     GetCandidatesFailed,
     #[error("session {0} not found")]
     SessionNotFound(usize),
+    #[error("rime worker thread is unavailable")]
+    WorkerUnavailable,
+}
+
+/// input schema provided by rime
+#[derive(Debug, Serialize)]
+pub struct Schema {
+    pub schema_id: String,
+    pub name: String,
+}
+
+/// metadata of current candidate menu page
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Menu {
+    /// max number of candidates in a page
+    pub page_size: usize,
+    /// current page number, starts from 0
+    pub page_no: usize,
+    /// if current page is the last page
+    pub is_last_page: bool,
+    /// index of highlighted candidate in current page
+    pub highlighted_index: usize,
 }
 
 #[derive(Debug)]
@@ -73,8 +120,12 @@ pub struct RimeResponse {
     pub is_incomplete: bool,
     /// partially submitted input
     pub submitted: String,
+    /// preedit of current composition
+    pub preedit: String,
     /// list of candidate provided by rime
     pub candidates: Vec<Candidate>,
+    /// menu paging info
+    pub menu: Menu,
 }
 
 impl Rime {
@@ -95,19 +146,66 @@ impl Rime {
         shared_data_dir: &str,
         user_data_dir: &str,
         log_dir: &str,
+        min_log_level: i32,
     ) -> Result<(), RimeError> {
         if Rime::is_initialized() {
             Err(RimeError::AlreadyInitialized)?
         }
+        let rime = Rime {
+            shared_data_dir: Mutex::new(shared_data_dir.to_owned()),
+            user_data_dir: Mutex::new(user_data_dir.to_owned()),
+            log_dir: log_dir.to_owned(),
+            min_log_level,
+            generation: AtomicUsize::new(0),
+            ready: AtomicBool::new(false),
+        };
+
+        let api = Self::get_api();
+        rime.with_traits(|traits| {
+            rime_call!(api->setup, traits);
+            rime_call!(api->initialize, traits);
+        })?;
+
+        RIME.set(rime).unwrap();
+        Ok(())
+    }
+
+    pub fn shared_data_dir(&self) -> String {
+        self.shared_data_dir.lock().unwrap().clone()
+    }
+
+    pub fn user_data_dir(&self) -> String {
+        self.user_data_dir.lock().unwrap().clone()
+    }
+
+    pub fn log_dir(&self) -> &str {
+        &self.log_dir
+    }
+
+    /// if maintenance is done and rime can be used
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// run maintenance after initialization, it may be very slow at first launch
+    pub fn maintain(&self) {
+        if !self.is_ready() {
+            self.run_maintenance(false);
+            self.ready.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// call librime with traits built from data dirs
+    fn with_traits(&self, f: impl FnOnce(&mut librime::RimeTraits)) -> Result<(), RimeError> {
         let mut traits = rime_struct!(librime::RimeTraits);
 
         // set dirs
-        traits.shared_data_dir = CString::new(shared_data_dir)?.into_raw();
-        traits.user_data_dir = CString::new(user_data_dir)?.into_raw();
+        traits.shared_data_dir = CString::new(self.shared_data_dir())?.into_raw();
+        traits.user_data_dir = CString::new(self.user_data_dir())?.into_raw();
         #[cfg(not(feature = "no_log_dir"))]
         {
-            traits.log_dir = CString::new(log_dir)?.into_raw();
-            traits.min_log_level = 2; // ERROR
+            traits.log_dir = CString::new(self.log_dir.as_str())?.into_raw();
+            traits.min_log_level = self.min_log_level;
         }
 
         // set name
@@ -117,12 +215,8 @@ impl Rime {
         // note: app_name is passed to glog as `const char*` without being copied to a std::string
         traits.app_name = APP_NAME.as_ptr() as *mut c_char;
 
-        let api = Self::get_api();
-        rime_call!(api->setup, &mut traits);
-        rime_call!(api->initialize, &mut traits);
-        if rime_call!(api->start_maintenance, 0) != 0 {
-            rime_call!(api->join_maintenance_thread);
-        }
+        f(&mut traits);
+
         unsafe {
             // retake pointer
             let _ = CString::from_raw(traits.shared_data_dir as *mut c_char);
@@ -133,49 +227,138 @@ impl Rime {
             let _ = CString::from_raw(traits.distribution_code_name as *mut c_char);
             let _ = CString::from_raw(traits.distribution_version as *mut c_char);
         }
+        Ok(())
+    }
 
-        RIME.set(Rime).unwrap();
+    /// run maintenance and wait until it is done, `full_check` rebuilds all changed data
+    fn run_maintenance(&self, full_check: bool) {
+        let api = Self::get_api();
+        if rime_call!(api->start_maintenance, full_check as i32) != 0 {
+            rime_call!(api->join_maintenance_thread);
+        }
+    }
+
+    fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// deploy changes of schemas and dictionaries, all sessions are destroyed
+    pub fn deploy(&self) -> Result<(), RimeError> {
+        self.reinitialize(None)
+    }
+
+    /// switch to other shared and user data dirs, all sessions are destroyed
+    pub fn switch_data_dirs(
+        &self,
+        shared_data_dir: &str,
+        user_data_dir: &str,
+    ) -> Result<(), RimeError> {
+        self.reinitialize(Some((shared_data_dir, user_data_dir)))
+    }
+
+    /// finalize rime, initialize it again with new dirs if given, then deploy
+    fn reinitialize(&self, dirs: Option<(&str, &str)>) -> Result<(), RimeError> {
+        let api = Self::get_api();
+        self.ready.store(false, Ordering::SeqCst);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        rime_call!(api->cleanup_all_sessions);
+        rime_call!(api->finalize);
+        if let Some((shared_data_dir, user_data_dir)) = dirs {
+            *self.shared_data_dir.lock().unwrap() = shared_data_dir.to_owned();
+            *self.user_data_dir.lock().unwrap() = user_data_dir.to_owned();
+        }
+        self.with_traits(|traits| rime_call!(api->initialize, traits))?;
+        self.run_maintenance(true);
+        // dictionaries may be changed by deploying
+        crate::dict::clear();
+        self.ready.store(true, Ordering::SeqCst);
         Ok(())
     }
 
     pub fn destroy(&self) {
         if RIME.get().is_some() {
             let api = Self::get_api();
+            self.generation.fetch_add(1, Ordering::SeqCst);
             rime_call!(api->cleanup_all_sessions);
             rime_call!(api->finalize);
         }
     }
 
-    pub fn get_candidates_from_context(
+    fn convert_candidate(
+        &self,
+        candidate: &librime::RimeCandidate,
+        order: usize,
+    ) -> Result<Candidate, RimeError> {
+        let text = unsafe {
+            CStr::from_ptr(candidate.text)
+                .to_str()
+                .map_err(|_| RimeError::GetCandidatesFailed)?
+                .to_owned()
+        };
+        let comment = unsafe {
+            (!candidate.comment.is_null()).then(|| {
+                match CStr::from_ptr(candidate.comment).to_str() {
+                    Ok(s) => s.to_string(),
+                    Err(e) => e.to_string(),
+                }
+            })
+        };
+        Ok(Candidate::new(text, comment, Some(order)))
+    }
+
+    fn get_candidates_from_context(
         &self,
         context: &librime::RimeContext,
     ) -> Result<Vec<Candidate>, RimeError> {
         let res = Mutex::new(Vec::new());
         for i in 0..context.menu.num_candidates {
             let candidate = unsafe { *context.menu.candidates.offset(i as isize) };
-            let text = unsafe {
-                CStr::from_ptr(candidate.text)
-                    .to_str()
-                    .map_err(|_| RimeError::GetCandidatesFailed)?
-                    .to_owned()
-            };
-            let comment = unsafe {
-                (!candidate.comment.is_null()).then(|| {
-                    match CStr::from_ptr(candidate.comment).to_str() {
-                        Ok(s) => s.to_string(),
-                        Err(e) => e.to_string(),
-                    }
-                })
-            };
             let order = (i + 1) as usize;
             res.lock()
                 .unwrap()
-                .push(Candidate::new(text, comment, Some(order)));
+                .push(self.convert_candidate(&candidate, order)?);
         }
         res.into_inner().map_err(|_| RimeError::GetCandidatesFailed)
     }
 
-    pub fn get_raw_input(&self, session_id: usize) -> Option<String> {
+    /// get candidates across pages with candidate list iterator
+    fn get_candidates_from_iterator(
+        &self,
+        session_id: usize,
+        limit: usize,
+    ) -> Result<Vec<Candidate>, RimeError> {
+        let api = Self::get_api();
+        let mut res = Vec::new();
+        let mut iterator: librime::RimeCandidateListIterator = unsafe { std::mem::zeroed() };
+        if rime_call!(api->candidate_list_begin, session_id, &mut iterator) == 0 {
+            return Ok(res);
+        }
+        while res.len() < limit && rime_call!(api->candidate_list_next, &mut iterator) != 0 {
+            let order = res.len() + 1;
+            match self.convert_candidate(&iterator.candidate, order) {
+                Ok(c) => res.push(c),
+                Err(e) => {
+                    rime_call!(api->candidate_list_end, &mut iterator);
+                    return Err(e);
+                }
+            }
+        }
+        rime_call!(api->candidate_list_end, &mut iterator);
+        Ok(res)
+    }
+
+    fn get_menu_from_context(&self, context: &librime::RimeContext) -> Menu {
+        let menu = &context.menu;
+        Menu {
+            page_size: menu.page_size.max(0) as usize,
+            page_no: menu.page_no.max(0) as usize,
+            // a menu without candidates has nothing to page
+            is_last_page: menu.is_last_page != 0 || menu.num_candidates == 0,
+            highlighted_index: menu.highlighted_candidate_index.max(0) as usize,
+        }
+    }
+
+    fn get_raw_input(&self, session_id: usize) -> Option<String> {
         let api = Self::get_api();
         let ptr = rime_call!(api->get_input, session_id);
         unsafe { CStr::from_ptr(ptr).to_str().ok().map(|s| s.to_owned()) }
@@ -197,18 +380,11 @@ impl Rime {
         ans
     }
 
-    fn get_joined_preedit(&self, context: &librime::RimeContext) -> Option<String> {
-        if !context.composition.preedit.is_null() {
-            unsafe {
-                let preedit = CStr::from_ptr(context.composition.preedit).to_str().ok()?;
-                Some(preedit.chars().filter(|c| c != &' ').collect())
-            }
-        } else {
-            None
-        }
+    fn get_preedit(&self, context: &librime::RimeContext) -> Option<String> {
+        to_owned_string(context.composition.preedit)
     }
 
-    pub fn get_response_from_session(&self, session_id: usize) -> Result<RimeResponse, RimeError> {
+    fn get_response_from_session(&self, session_id: usize) -> Result<RimeResponse, RimeError> {
         let api = Self::get_api();
         if rime_call!(api->find_session, session_id) == 0 {
             return Err(RimeError::SessionNotFound(session_id));
@@ -217,11 +393,12 @@ impl Rime {
         let mut context = rime_struct!(librime::RimeContext);
         rime_call!(api->get_context, session_id, &mut context);
 
+        // get paging info
+        let menu = self.get_menu_from_context(&context);
         // get partially submitted text
-        let preedit = self.get_joined_preedit(&context);
-        let submitted = preedit
-            .map(|s| RAW_RE.replace_all(s.as_ref(), "").to_string())
-            .unwrap_or_default();
+        let preedit = self.get_preedit(&context).unwrap_or_default();
+        let joined_preedit: String = preedit.chars().filter(|c| c != &' ').collect();
+        let submitted = RAW_RE.replace_all(&joined_preedit, "").to_string();
         // note: must call it to consume commit text
         let commit_text = self.get_commit_text(session_id);
         // get candidates vec
@@ -242,77 +419,227 @@ impl Rime {
         candidates.map(|candidates| RimeResponse {
             is_incomplete,
             submitted,
+            preedit,
             candidates,
+            menu,
         })
     }
 
-    pub fn create_session(&self) -> usize {
+    /// create a new session, it will be destroyed when dropped
+    pub fn create_session(&self) -> RimeSession {
         let api = Self::get_api();
-        rime_call!(api->create_session)
+        RimeSession {
+            id: rime_call!(api->create_session),
+            generation: self.generation(),
+        }
     }
 
-    /// return if session exists
-    pub fn find_session(&self, session_id: usize) -> bool {
+    /// list all available schemas
+    pub fn get_schema_list(&self) -> Vec<Schema> {
         let api = Self::get_api();
-        rime_call!(api->find_session, session_id) != 0
+        let mut list: librime::RimeSchemaList = unsafe { std::mem::zeroed() };
+        if rime_call!(api->get_schema_list, &mut list) == 0 {
+            return Vec::new();
+        }
+        let schemas = (0..list.size)
+            .map(|i| unsafe { *list.list.add(i) })
+            .filter_map(|item| {
+                let schema_id = to_owned_string(item.schema_id)?;
+                let name = to_owned_string(item.name).unwrap_or_else(|| schema_id.clone());
+                Some(Schema { schema_id, name })
+            })
+            .collect();
+        rime_call!(api->free_schema_list, &mut list);
+        schemas
+    }
+
+    /// if a schema is in the schema list
+    pub fn has_schema(&self, schema_id: &str) -> bool {
+        self.get_schema_list()
+            .iter()
+            .any(|schema| schema.schema_id == schema_id)
     }
 
-    pub fn process_key(&self, session_id: usize, key: i32) {
+    /// get a string in config of a schema, e.g. `translator/dictionary`
+    pub fn get_schema_string(&self, schema_id: &str, key: &str) -> Option<String> {
         let api = Self::get_api();
-        rime_call!(api->process_key, session_id, key, 0);
+        let schema_id = CString::new(schema_id).ok()?;
+        let key = CString::new(key).ok()?;
+        let mut config: librime::RimeConfig = unsafe { std::mem::zeroed() };
+        if rime_call!(api->schema_open, schema_id.as_ptr(), &mut config) == 0 {
+            return None;
+        }
+        let value = to_owned_string(rime_call!(api->config_get_cstring, &mut config, key.as_ptr()));
+        rime_call!(api->config_close, &mut config);
+        value
     }
 
-    pub fn process_str(&self, session_id: usize, keys: &str) {
+    pub fn sync_user_data(&self) {
         let api = Self::get_api();
+        rime_call!(api->sync_user_data);
+        rime_call!(api->join_maintenance_thread);
+    }
+}
+
+/// an owned rime session, destroyed on drop
+#[derive(Debug)]
+pub struct RimeSession {
+    id: usize,
+    /// generation of rime when this session is created
+    generation: usize,
+}
+
+impl RimeSession {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// return if session still exists in librime
+    pub fn exists(&self) -> bool {
+        let api = Rime::get_api();
+        Self::is_valid(self.generation) && rime_call!(api->find_session, self.id) != 0
+    }
+
+    /// session id may be reused after rime is re-initialized
+    fn is_valid(generation: usize) -> bool {
+        Rime::is_initialized() && Rime::global().generation() == generation
+    }
+
+    pub fn process_key(&self, key: i32) {
+        let api = Rime::get_api();
+        rime_call!(api->process_key, self.id, key, 0);
+    }
+
+    pub fn process_str(&self, keys: &str) {
+        let api = Rime::get_api();
         for key in keys.bytes() {
-            rime_call!(api->process_key, session_id, key as i32, 0);
+            rime_call!(api->process_key, self.id, key as i32, 0);
         }
     }
 
-    pub fn delete_keys(&self, session_id: usize, len: usize) {
-        let api = Self::get_api();
+    pub fn delete_keys(&self, len: usize) {
+        let api = Rime::get_api();
         for _ in 0..len {
-            rime_call!(api->process_key, session_id, KEY_BACKSPACE, 0);
+            rime_call!(api->process_key, self.id, KEY_BACKSPACE, 0);
         }
     }
 
-    pub fn destroy_session(&self, session_id: usize) {
-        let api = Self::get_api();
-        rime_call!(api->destroy_session, session_id);
+    pub fn clear_composition(&self) {
+        let api = Rime::get_api();
+        rime_call!(api->process_key, self.id, KEY_ESCAPE, 0);
+        rime_call!(api->clear_composition, self.id);
     }
 
-    pub fn clear_composition(&self, session_id: usize) {
-        let api = Self::get_api();
-        rime_call!(api->process_key, session_id, KEY_ESCAPE, 0);
-        rime_call!(api->clear_composition, session_id);
+    pub fn get_raw_input(&self) -> Option<String> {
+        Rime::global().get_raw_input(self.id)
     }
 
-    pub fn sync_user_data(&self) {
-        let api = Self::get_api();
-        rime_call!(api->sync_user_data);
-        rime_call!(api->join_maintenance_thread);
+    pub fn get_current_schema(&self) -> Option<String> {
+        let api = Rime::get_api();
+        let mut buf = [0 as c_char; SCHEMA_ID_MAX_LEN];
+        let ok = rime_call!(api->get_current_schema, self.id, buf.as_mut_ptr(), buf.len());
+        (ok != 0).then(|| to_owned_string(buf.as_ptr())).flatten()
+    }
+
+    pub fn get_option(&self, option: &str) -> Result<bool, RimeError> {
+        let api = Rime::get_api();
+        let option = CString::new(option)?;
+        Ok(rime_call!(api->get_option, self.id, option.as_ptr()) != 0)
+    }
+
+    pub fn set_option(&self, option: &str, value: bool) -> Result<(), RimeError> {
+        let api = Rime::get_api();
+        let option = CString::new(option)?;
+        rime_call!(api->set_option, self.id, option.as_ptr(), value as i32);
+        Ok(())
+    }
+
+    /// select schema, note that current composition is cleared
+    pub fn select_schema(&self, schema_id: &str) -> Result<bool, RimeError> {
+        // rime selects an empty schema for unknown ids, which gives no candidates
+        if !Rime::global().has_schema(schema_id) {
+            return Ok(false);
+        }
+        let api = Rime::get_api();
+        let schema_id = CString::new(schema_id)?;
+        Ok(rime_call!(api->select_schema, self.id, schema_id.as_ptr()) != 0)
+    }
+
+    pub fn get_response(&self) -> Result<RimeResponse, RimeError> {
+        Rime::global().get_response_from_session(self.id)
+    }
+
+    /// get at most `limit` candidates of all pages
+    pub fn get_all_candidates(&self, limit: usize) -> Result<Vec<Candidate>, RimeError> {
+        Rime::global().get_candidates_from_iterator(self.id, limit)
+    }
+
+    /// select a candidate and commit, so that user dictionary learns it,
+    /// nothing is done if candidate at `index` is not `text`
+    pub fn commit_candidate(&self, index: usize, text: &str) -> Result<bool, RimeError> {
+        let candidates = self.get_all_candidates(index + 1)?;
+        if !candidates.get(index).is_some_and(|c| c.text == text) {
+            return Ok(false);
+        }
+        let api = Rime::get_api();
+        if rime_call!(api->select_candidate, self.id, index) == 0 {
+            return Ok(false);
+        }
+        // nothing is left to select if the candidate consumes the whole input
+        let mut context = rime_struct!(librime::RimeContext);
+        rime_call!(api->get_context, self.id, &mut context);
+        let consumed = context.menu.num_candidates == 0;
+        rime_call!(api->free_context, &mut context);
+        if consumed {
+            // user dictionary is updated on commit
+            rime_call!(api->commit_composition, self.id);
+        } else {
+            // committing the rest would make user dictionary learn words not picked
+            self.clear_composition();
+        }
+        // text is already inserted by client
+        let _ = Rime::global().get_commit_text(self.id);
+        Ok(true)
     }
 }
 
-#[test]
-fn test_get_candidates() {
+impl Drop for RimeSession {
+    fn drop(&mut self) {
+        // sessions may be dropped in async tasks, so destroy it on the rime thread
+        let (id, generation) = (self.id, self.generation);
+        worker::spawn(move || {
+            if Self::is_valid(generation) {
+                let api = Rime::get_api();
+                rime_call!(api->destroy_session, id);
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn test_get_candidates() {
     let shared_data_dir = crate::utils::rime_default_shared_data_dir();
     let temp_dir = std::env::temp_dir();
-    let temp_dir = temp_dir.to_str().unwrap();
 
-    // init
-    Rime::init(shared_data_dir, temp_dir, temp_dir).unwrap();
-    let rime = Rime::global();
-    // simulate typing
-    let keys = vec![b'w', b'l', b'h'];
-    let session_id = rime.create_session();
-    for key in keys {
-        rime.process_key(session_id, key as i32);
-    }
-    let res = rime.get_response_from_session(session_id).unwrap();
-    assert!(res.candidates.len() != 0);
-    rime.destroy_session(session_id);
+    let res = worker::call(move || {
+        let temp_dir = temp_dir.to_str().unwrap();
+        // init
+        Rime::init(shared_data_dir, temp_dir, temp_dir, 2).unwrap();
+        let rime = Rime::global();
+        rime.maintain();
+        // simulate typing
+        let keys = vec![b'w', b'l', b'h'];
+        let session = rime.create_session();
+        for key in keys {
+            session.process_key(key as i32);
+        }
+        session.get_response()
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    assert!(!res.candidates.is_empty());
 
     // destroy
-    rime.destroy();
+    worker::call(|| Rime::global().destroy()).await.unwrap();
 }
@@ -0,0 +1,784 @@
+/// a pair of delimiters, like `/* */` or `" "`
+struct Delimiter {
+    open: &'static str,
+    close: &'static str,
+    /// if it can span multiple lines
+    multiline: bool,
+}
+
+const fn delim(open: &'static str, close: &'static str, multiline: bool) -> Delimiter {
+    Delimiter {
+        open,
+        close,
+        multiline,
+    }
+}
+
+/// comment and string syntax of a language, longer delimiters go first
+pub struct Syntax {
+    line_comments: &'static [&'static str],
+    block_comments: &'static [Delimiter],
+    strings: &'static [Delimiter],
+    /// if block comments can be nested, like `/* /* */ */` of Rust
+    nested_comments: bool,
+    /// char literals, byte strings and raw strings of Rust, `'` may be a lifetime
+    rust_literals: bool,
+}
+
+/// what the end of scanned text is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    Code,
+    Comment,
+    String,
+}
+
+const C_LIKE: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comments: &[delim("/*", "*/", true)],
+    strings: &[delim("\"", "\"", false), delim("'", "'", false)],
+    nested_comments: false,
+    rust_literals: false,
+};
+
+const RUST: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comments: &[delim("/*", "*/", true)],
+    // `'` is handled as char literals because of lifetimes
+    strings: &[delim("\"", "\"", true)],
+    nested_comments: true,
+    rust_literals: true,
+};
+
+/// C-like languages with backtick strings
+const BACKTICK: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comments: &[delim("/*", "*/", true)],
+    strings: &[
+        delim("\"", "\"", false),
+        delim("'", "'", false),
+        delim("`", "`", true),
+    ],
+    nested_comments: false,
+    rust_literals: false,
+};
+
+const PYTHON: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comments: &[],
+    strings: &[
+        delim("\"\"\"", "\"\"\"", true),
+        delim("'''", "'''", true),
+        delim("\"", "\"", false),
+        delim("'", "'", false),
+    ],
+    nested_comments: false,
+    rust_literals: false,
+};
+
+const HASH: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comments: &[],
+    strings: &[delim("\"", "\"", true), delim("'", "'", true)],
+    nested_comments: false,
+    rust_literals: false,
+};
+
+const LUA: Syntax = Syntax {
+    line_comments: &["--"],
+    block_comments: &[delim("--[[", "]]", true)],
+    strings: &[
+        delim("[[", "]]", true),
+        delim("\"", "\"", false),
+        delim("'", "'", false),
+    ],
+    nested_comments: false,
+    rust_literals: false,
+};
+
+const SQL: Syntax = Syntax {
+    line_comments: &["--"],
+    block_comments: &[delim("/*", "*/", true)],
+    strings: &[delim("'", "'", true)],
+    nested_comments: false,
+    rust_literals: false,
+};
+
+const HASKELL: Syntax = Syntax {
+    line_comments: &["--"],
+    block_comments: &[delim("{-", "-}", true)],
+    strings: &[delim("\"", "\"", false)],
+    nested_comments: true,
+    rust_literals: false,
+};
+
+const LISP: Syntax = Syntax {
+    line_comments: &[";"],
+    block_comments: &[],
+    strings: &[delim("\"", "\"", true)],
+    nested_comments: false,
+    rust_literals: false,
+};
+
+const XML: Syntax = Syntax {
+    line_comments: &[],
+    block_comments: &[delim("<!--", "-->", true)],
+    strings: &[],
+    nested_comments: false,
+    rust_literals: false,
+};
+
+/// syntax of a LSP languageId, `None` if unknown
+pub fn syntax_of(language_id: &str) -> Option<&'static Syntax> {
+    let syntax = match language_id {
+        "c" | "cpp" | "objective-c" | "objective-cpp" | "cuda" | "java" | "csharp" | "kotlin"
+        | "scala" | "swift" | "dart" | "php" | "zig" => &C_LIKE,
+        "rust" => &RUST,
+        "go" | "javascript" | "javascriptreact" | "typescript" | "typescriptreact" | "vue"
+        | "svelte" => &BACKTICK,
+        "python" => &PYTHON,
+        "shellscript" | "sh" | "bash" | "zsh" | "fish" | "ruby" | "perl" | "r" | "toml"
+        | "yaml" | "dockerfile" | "makefile" | "cmake" | "nix" | "elixir" => &HASH,
+        "lua" => &LUA,
+        "sql" => &SQL,
+        "haskell" | "elm" => &HASKELL,
+        "lisp" | "scheme" | "clojure" | "racket" | "commonlisp" => &LISP,
+        "html" | "xml" => &XML,
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+/// where a scanner is in a line
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Code,
+    LineComment,
+    /// index of the delimiter, and depth of nested comments
+    BlockComment(usize, usize),
+    /// index of the delimiter
+    String(usize),
+    /// number of `#` around a raw string of Rust
+    RawString(usize),
+}
+
+/// scan a document chunk by chunk, like chunks of a rope
+#[derive(Clone)]
+pub struct Scanner<'a> {
+    syntax: &'a Syntax,
+    state: State,
+    /// the current line, if it does not end in chunks fed so far
+    line: String,
+}
+
+impl Syntax {
+    pub fn scanner(&self) -> Scanner<'_> {
+        Scanner {
+            syntax: self,
+            state: State::Code,
+            line: String::new(),
+        }
+    }
+
+    /// scan from beginning of a document, find what the end of text is in
+    #[cfg(test)]
+    fn context(&self, text: &str) -> Context {
+        let mut scanner = self.scanner();
+        scanner.feed(text);
+        scanner.finish()
+    }
+}
+
+/// split chunks into complete lines, `pending` keeps the line that does not end yet
+///
+/// delimiters never span lines, so lines can be scanned one by one
+fn feed_lines(pending: &mut String, mut text: &str, mut scan: impl FnMut(&str)) {
+    while let Some(n) = text.find('\n') {
+        let (line, rest) = text.split_at(n + 1);
+        if pending.is_empty() {
+            scan(line);
+        } else {
+            pending.push_str(line);
+            scan(pending);
+            pending.clear();
+        }
+        text = rest;
+    }
+    pending.push_str(text);
+}
+
+/// length of a Rust char literal at the beginning of `text`, like `'a'` or `'\''`
+fn char_literal(text: &str) -> Option<usize> {
+    let body = text.strip_prefix('\'')?;
+    let len = match body.strip_prefix('\\') {
+        // escapes like `'\n'` and `'\u{1F600}'`
+        Some(escaped) => {
+            let ch = escaped.chars().next()?.len_utf8();
+            1 + ch + escaped[ch..].find('\'')?
+        }
+        None => body.chars().next().filter(|c| *c != '\'')?.len_utf8(),
+    };
+    body[len..].starts_with('\'').then_some(len + 2)
+}
+
+impl Scanner<'_> {
+    /// if it scans comments and strings of the syntax
+    pub fn is_for(&self, syntax: &Syntax) -> bool {
+        std::ptr::eq(self.syntax, syntax)
+    }
+
+    /// scan next chunk of the document
+    pub fn feed(&mut self, text: &str) {
+        let mut pending = std::mem::take(&mut self.line);
+        feed_lines(&mut pending, text, |line| self.scan_line(line));
+        self.line = pending;
+    }
+
+    /// find what the end of text fed is in
+    pub fn finish(mut self) -> Context {
+        let line = std::mem::take(&mut self.line);
+        self.scan_line(&line);
+        match self.state {
+            State::Code => Context::Code,
+            State::LineComment | State::BlockComment(..) => Context::Comment,
+            State::String(_) | State::RawString(_) => Context::String,
+        }
+    }
+
+    fn scan_line(&mut self, line: &str) {
+        let mut i = 0;
+        while i < line.len() {
+            i += self.step(&line[i..]);
+        }
+    }
+
+    /// move over the beginning of `rest`, return length of it
+    fn step(&mut self, rest: &str) -> usize {
+        let syntax = self.syntax;
+        let char_len = rest.chars().next().map_or(1, char::len_utf8);
+        match self.state {
+            State::Code => {
+                if let Some((n, d)) = syntax
+                    .block_comments
+                    .iter()
+                    .enumerate()
+                    .find(|(_, d)| rest.starts_with(d.open))
+                {
+                    self.state = State::BlockComment(n, 1);
+                    d.open.len()
+                } else if syntax.line_comments.iter().any(|c| rest.starts_with(c)) {
+                    self.state = State::LineComment;
+                    char_len
+                } else if let Some((n, d)) = syntax
+                    .strings
+                    .iter()
+                    .enumerate()
+                    .find(|(_, d)| rest.starts_with(d.open))
+                {
+                    self.state = State::String(n);
+                    d.open.len()
+                } else if syntax.rust_literals && rest.starts_with('\'') {
+                    // a lifetime if it is not a char literal
+                    char_literal(rest).unwrap_or(1)
+                } else if syntax.rust_literals
+                    && rest.starts_with(|c: char| c == '_' || c.is_alphanumeric())
+                {
+                    let word = rest
+                        .find(|c: char| c != '_' && !c.is_alphanumeric())
+                        .unwrap_or(rest.len());
+                    let hashes = rest[word..].chars().take_while(|c| *c == '#').count();
+                    let raw = matches!(&rest[..word], "r" | "br" | "cr")
+                        && rest[word + hashes..].starts_with('"');
+                    if raw {
+                        self.state = State::RawString(hashes);
+                        return word + hashes + 1;
+                    }
+                    word
+                } else {
+                    char_len
+                }
+            }
+            State::LineComment => match rest.find('\n') {
+                Some(0) => {
+                    self.state = State::Code;
+                    1
+                }
+                Some(n) => n,
+                None => rest.len(),
+            },
+            State::BlockComment(n, depth) => {
+                let d = &syntax.block_comments[n];
+                if rest.starts_with(d.close) {
+                    self.state = match depth {
+                        1 => State::Code,
+                        _ => State::BlockComment(n, depth - 1),
+                    };
+                    d.close.len()
+                } else if syntax.nested_comments && rest.starts_with(d.open) {
+                    self.state = State::BlockComment(n, depth + 1);
+                    d.open.len()
+                } else {
+                    char_len
+                }
+            }
+            State::String(n) => {
+                let d = &syntax.strings[n];
+                if let Some(escaped) = rest.strip_prefix('\\') {
+                    1 + escaped.chars().next().map_or(0, char::len_utf8)
+                } else if rest.starts_with(d.close) {
+                    self.state = State::Code;
+                    d.close.len()
+                } else {
+                    if rest.starts_with('\n') && !d.multiline {
+                        // unterminated string ends at line end
+                        self.state = State::Code;
+                    }
+                    char_len
+                }
+            }
+            State::RawString(hashes) => {
+                let closed = rest.strip_prefix('"').is_some_and(|r| {
+                    r.len() >= hashes && r.bytes().take(hashes).all(|b| b == b'#')
+                });
+                if closed {
+                    self.state = State::Code;
+                    return 1 + hashes;
+                }
+                char_len
+            }
+        }
+    }
+}
+
+/// what the end of a markup document is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Markup {
+    Text,
+    /// code block, inline code or verbatim
+    Code,
+    Math,
+    FrontMatter,
+}
+
+/// markup language of a LSP languageId, `None` if it is not markdown or LaTeX
+fn markup_state(language_id: &str) -> Option<MarkupState> {
+    match language_id {
+        "markdown" | "markdown.mdx" | "mdx" | "quarto" | "rmd" => {
+            Some(MarkupState::Markdown(None, true))
+        }
+        "latex" | "tex" | "plaintex" | "context" => Some(MarkupState::Latex(None)),
+        _ => None,
+    }
+}
+
+/// what is open at the beginning of a line
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MarkupState {
+    /// open block, and if it is the first line
+    Markdown(Option<Block>, bool),
+    /// closing delimiter and context of an open region
+    Latex(Option<(String, Markup)>),
+}
+
+/// scan a markup document chunk by chunk, it can be cloned to resume later
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkupScanner {
+    state: MarkupState,
+    /// the current line, if it does not end in chunks fed so far
+    line: String,
+}
+
+/// scanner of a markup document of a LSP languageId, `None` if it is not markdown or LaTeX
+pub fn markup_scanner(language_id: &str) -> Option<MarkupScanner> {
+    Some(MarkupScanner {
+        state: markup_state(language_id)?,
+        line: String::new(),
+    })
+}
+
+/// scan a whole markup document, find what the end of text is in
+#[cfg(test)]
+fn markup_context(language_id: &str, text: &str) -> Option<Markup> {
+    let mut scanner = markup_scanner(language_id)?;
+    scanner.feed(text);
+    Some(scanner.finish(""))
+}
+
+impl MarkupScanner {
+    /// if it scans the markup language of a LSP languageId
+    pub fn is_for(&self, language_id: &str) -> bool {
+        markup_state(language_id).is_some_and(|state| {
+            std::mem::discriminant(&state) == std::mem::discriminant(&self.state)
+        })
+    }
+
+    /// scan next chunk of the document
+    pub fn feed(&mut self, text: &str) {
+        let mut pending = std::mem::take(&mut self.line);
+        feed_lines(&mut pending, text, |line| {
+            let line = line.strip_suffix('\n').unwrap_or(line);
+            match &mut self.state {
+                MarkupState::Markdown(block, first) => {
+                    *block = markdown_line(block.take(), *first, line);
+                    *first = false;
+                }
+                MarkupState::Latex(region) => {
+                    latex_line(region, line);
+                }
+            }
+        });
+        self.line = pending;
+    }
+
+    /// find what the end of text fed is in, `rest` is the text after it in the same line
+    pub fn finish(mut self, rest: &str) -> Markup {
+        let line = std::mem::take(&mut self.line);
+        match &mut self.state {
+            MarkupState::Markdown(Some(block), _) => match block {
+                Block::FrontMatter(_) => Markup::FrontMatter,
+                Block::Fence(..) => Markup::Code,
+                Block::Math => Markup::Math,
+            },
+            MarkupState::Markdown(None, _) if code_fence(&line).is_some() => Markup::Code,
+            MarkupState::Markdown(None, _) => markdown_inline_context(&line, rest),
+            MarkupState::Latex(region) => {
+                latex_line(region, &line);
+                region.as_ref().map_or(Markup::Text, |(_, ctx)| *ctx)
+            }
+        }
+    }
+}
+
+/// find `pat` in `text`, skip characters escaped by backslash
+fn find_unescaped(text: &str, pat: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, ch) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if text[i..].starts_with(pat) {
+            return Some(i);
+        } else if ch == '\\' {
+            escaped = true;
+        }
+    }
+    None
+}
+
+/// opening fence of a fenced code block, like ```` ```rust ```` or `~~~`
+fn code_fence(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let line = trimmed;
+    let ch = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = line.chars().take_while(|c| *c == ch).count();
+    (len >= 3).then_some((ch, len))
+}
+
+/// block of markdown that spans lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Block {
+    FrontMatter(&'static str),
+    Fence(char, usize),
+    Math,
+}
+
+/// block that is open after a complete line of markdown
+fn markdown_line(block: Option<Block>, first: bool, line: &str) -> Option<Block> {
+    let trimmed = line.trim();
+    match block {
+        Some(Block::FrontMatter(close)) => {
+            let closed = trimmed == close || (close == "---" && trimmed == "...");
+            (!closed).then_some(Block::FrontMatter(close))
+        }
+        Some(Block::Fence(ch, len)) => {
+            let closed = code_fence(line).is_some_and(|(c, l)| c == ch && l >= len)
+                && trimmed.chars().all(|c| c == ch);
+            (!closed).then_some(Block::Fence(ch, len))
+        }
+        Some(Block::Math) => (!trimmed.ends_with("$$")).then_some(Block::Math),
+        None if first && trimmed == "---" => Some(Block::FrontMatter("---")),
+        None if first && trimmed == "+++" => Some(Block::FrontMatter("+++")),
+        None => match code_fence(line) {
+            Some((ch, len)) => Some(Block::Fence(ch, len)),
+            // `$$` without closing `$$` in the same line
+            None if trimmed.starts_with("$$") && !trimmed[2..].contains("$$") => Some(Block::Math),
+            None => None,
+        },
+    }
+}
+
+/// closing `$` of inline math, it follows a non-space and is not followed by a digit
+fn inline_math_end(body: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, ch) in body.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if ch == '\\' {
+            escaped = true;
+        } else if ch == '$'
+            && body[..i]
+                .chars()
+                .next_back()
+                .is_some_and(|c| !c.is_whitespace())
+            && !body[i + 1..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// inline code and math at the end of `line`, `after` is the text after it in the same line
+fn markdown_inline_context(line: &str, after: &str) -> Markup {
+    let whole = format!("{line}{after}");
+    let end = line.len();
+    let mut i = 0;
+    while i < end {
+        let rest = &whole[i..];
+        // (length of delimiters and body, context), `None` if it is not an opening
+        let span = if let Some(escaped) = rest.strip_prefix('\\') {
+            i += 1 + escaped.chars().next().map_or(0, char::len_utf8);
+            continue;
+        } else if rest.starts_with('`') {
+            let len = rest.chars().take_while(|c| *c == '`').count();
+            let close = rest[len..].find(&rest[..len]).map(|n| len + n + len);
+            Some((close, Markup::Code))
+        } else if let Some(body) = rest.strip_prefix("$$") {
+            let close = find_unescaped(body, "$$").map(|n| 2 + n + 2);
+            Some((close, Markup::Math))
+        } else if let Some(body) = rest
+            .strip_prefix('$')
+            .filter(|body| !body.starts_with(char::is_whitespace))
+        {
+            // unclosed `$` is literal, like `$5` or `$HOME`
+            inline_math_end(body).map(|n| (Some(1 + n + 1), Markup::Math))
+        } else {
+            None
+        };
+        match span {
+            // the end is inside the span
+            Some((None, ctx)) => return ctx,
+            Some((Some(len), ctx)) if i + len > end => return ctx,
+            Some((Some(len), _)) => i += len,
+            None => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    Markup::Text
+}
+
+const LATEX_VERBATIM_ENVS: &[&str] = &["verbatim", "verbatim*", "lstlisting", "minted", "comment"];
+
+const LATEX_MATH_ENVS: &[&str] = &[
+    "math",
+    "displaymath",
+    "equation",
+    "equation*",
+    "align",
+    "align*",
+    "alignat",
+    "alignat*",
+    "flalign",
+    "flalign*",
+    "gather",
+    "gather*",
+    "multline",
+    "multline*",
+    "eqnarray",
+    "eqnarray*",
+];
+
+/// scan a line of LaTeX, `region` is open at the beginning and the end of it
+fn latex_line(region: &mut Option<(String, Markup)>, line: &str) {
+    let mut i = 0;
+    while i < line.len() {
+        let rest = &line[i..];
+        if let Some((close, ctx)) = region {
+            let end = match ctx {
+                Markup::Code => rest.find(close.as_str()),
+                _ => find_unescaped(rest, close),
+            };
+            match end {
+                Some(n) => i += n + close.len(),
+                None => return,
+            }
+            *region = None;
+            continue;
+        }
+        // (open, close, context), `None` if it is not an opening
+        let opening = if let Some(env) = rest
+            .strip_prefix("\\begin{")
+            .and_then(|r| r.split_once('}'))
+            .map(|(env, _)| env)
+        {
+            let ctx = if LATEX_VERBATIM_ENVS.contains(&env) {
+                Some(Markup::Code)
+            } else if LATEX_MATH_ENVS.contains(&env) {
+                Some(Markup::Math)
+            } else {
+                None
+            };
+            let open_len = "\\begin{}".len() + env.len();
+            match ctx {
+                Some(ctx) => Some((open_len, format!("\\end{{{env}}}"), ctx)),
+                None => {
+                    i += open_len;
+                    continue;
+                }
+            }
+        } else if let Some(d) = rest.strip_prefix("\\verb").and_then(|r| r.chars().next()) {
+            Some(("\\verb".len() + d.len_utf8(), d.to_string(), Markup::Code))
+        } else if rest.starts_with("\\[") {
+            Some((2, "\\]".to_string(), Markup::Math))
+        } else if rest.starts_with("\\(") {
+            Some((2, "\\)".to_string(), Markup::Math))
+        } else if rest.starts_with("$$") {
+            Some((2, "$$".to_string(), Markup::Math))
+        } else if rest.starts_with('$') {
+            Some((1, "$".to_string(), Markup::Math))
+        } else {
+            None
+        };
+        if let Some((open_len, close, ctx)) = opening {
+            i += open_len;
+            *region = Some((close, ctx));
+        } else if let Some(escaped) = rest.strip_prefix('\\') {
+            // skip escaped characters like `\%` and `\$`
+            i += 1 + escaped.chars().next().map_or(0, char::len_utf8);
+        } else if rest.starts_with('%') {
+            // comment is text
+            return;
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+}
+
+#[test]
+fn test_context() {
+    let rust = syntax_of("rust").unwrap();
+    assert_eq!(rust.context("fn main() {"), Context::Code);
+    assert_eq!(rust.context("// nihao"), Context::Comment);
+    assert_eq!(rust.context("// nihao\nlet a"), Context::Code);
+    assert_eq!(rust.context("/* a\n * b"), Context::Comment);
+    assert_eq!(rust.context("/* a */ let b"), Context::Code);
+    assert_eq!(rust.context("let s = \"ni"), Context::String);
+    assert_eq!(rust.context("let s = \"\\\" // ni"), Context::String);
+    assert_eq!(rust.context("let s = \"\"; // ni"), Context::Comment);
+    assert_eq!(rust.context("fn f<'a>(s: &'a str) { ni"), Context::Code);
+    assert_eq!(rust.context("let c = '\"'; ni"), Context::Code);
+    assert_eq!(rust.context("let c = '\\''; // ni"), Context::Comment);
+    assert_eq!(rust.context("let c = b'\\x7f'; ni"), Context::Code);
+    assert_eq!(rust.context("let s = r#\"a\" ni"), Context::String);
+    assert_eq!(rust.context("let s = r#\"a\"\"#; ni"), Context::Code);
+    assert_eq!(rust.context("let s = br\"\\\"; ni"), Context::Code);
+    assert_eq!(rust.context("let bar = \"ni"), Context::String);
+    assert_eq!(rust.context("/* a /* b */ ni"), Context::Comment);
+    assert_eq!(rust.context("/* a /* b */ */ ni"), Context::Code);
+    assert_eq!(
+        syntax_of("c").unwrap().context("/* a /* b */ ni"),
+        Context::Code
+    );
+    let python = syntax_of("python").unwrap();
+    assert_eq!(python.context("'''doc\nni"), Context::String);
+    assert_eq!(python.context("s = 'a\nni"), Context::Code);
+    assert_eq!(python.context("s = '#' ni"), Context::Code);
+    assert_eq!(python.context("s = '#' # ni"), Context::Comment);
+    let lua = syntax_of("lua").unwrap();
+    assert_eq!(lua.context("--[[ a\nni"), Context::Comment);
+    assert_eq!(lua.context("--[[ a ]] ni"), Context::Code);
+    assert!(syntax_of("markdown").is_none());
+}
+
+#[test]
+fn test_scanner() {
+    let rust = syntax_of("rust").unwrap();
+    let text = "let s = r#\"a\n\"#; /* b /*\n c */ */ let c = '\\''; // 你好";
+    // delimiters and lines split by chunks
+    for size in 1..text.len() {
+        let mut scanner = rust.scanner();
+        let mut chunk = String::new();
+        for ch in text.chars() {
+            chunk.push(ch);
+            if chunk.len() >= size {
+                scanner.feed(&chunk);
+                chunk.clear();
+            }
+        }
+        scanner.feed(&chunk);
+        assert_eq!(scanner.finish(), Context::Comment);
+    }
+}
+
+#[test]
+fn test_markdown_context() {
+    let md = |text| markup_context("markdown", text).unwrap();
+    assert_eq!(md("你好 ni"), Markup::Text);
+    assert_eq!(md("---\ntitle: ni"), Markup::FrontMatter);
+    assert_eq!(md("---\ntitle: a\n---\nni"), Markup::Text);
+    assert_eq!(md("a\n---\nni"), Markup::Text);
+    assert_eq!(md("```rust\nlet ni"), Markup::Code);
+    assert_eq!(md("```rust\nlet a;\n```\nni"), Markup::Text);
+    assert_eq!(md("````\n```\nni"), Markup::Code);
+    assert_eq!(md("```ru"), Markup::Code);
+    assert_eq!(md("use `ni"), Markup::Code);
+    assert_eq!(md("use `a` ni"), Markup::Text);
+    assert_eq!(md("use ``a ` ni"), Markup::Code);
+    assert_eq!(md("math $x + ni"), Markup::Text);
+    assert_eq!(md("math $x$ ni"), Markup::Text);
+    assert_eq!(md("价格 $5 ni"), Markup::Text);
+    assert_eq!(md("$HOME 下的 ni"), Markup::Text);
+    assert_eq!(md("$5 and $10 ni"), Markup::Text);
+    assert_eq!(md("cost \\$5 ni"), Markup::Text);
+    assert_eq!(md("$$\nx = ni"), Markup::Math);
+    assert_eq!(md("$$\nx\n$$\nni"), Markup::Text);
+    assert_eq!(md("$$x$$ ni"), Markup::Text);
+    // closing delimiters after the end in the same line
+    let md_in = |text, after| {
+        let mut scanner = markup_scanner("markdown").unwrap();
+        scanner.feed(text);
+        scanner.finish(after)
+    };
+    assert_eq!(md_in("math $x + ni", "$ end"), Markup::Math);
+    assert_eq!(md_in("math $x + ni", " $5"), Markup::Text);
+    assert_eq!(md_in("use `ni", "` end"), Markup::Code);
+    assert_eq!(md_in("$$x + ni", "$$"), Markup::Math);
+    // resume a cloned scanner
+    let mut scanner = markup_scanner("markdown").unwrap();
+    scanner.feed(
+        "---
+a: b
+---
+```
+",
+    );
+    assert!(scanner.is_for("markdown") && !scanner.is_for("latex"));
+    let mut resumed = scanner.clone();
+    resumed.feed("let ni");
+    assert_eq!(resumed.finish(""), Markup::Code);
+    scanner.feed(
+        "```
+ni",
+    );
+    assert_eq!(scanner.finish(""), Markup::Text);
+}
+
+#[test]
+fn test_latex_context() {
+    let tex = |text| markup_context("latex", text).unwrap();
+    assert_eq!(tex("你好 ni"), Markup::Text);
+    assert_eq!(tex("$x + ni"), Markup::Math);
+    assert_eq!(tex("$x$ ni"), Markup::Text);
+    assert_eq!(tex("\\$ ni"), Markup::Text);
+    assert_eq!(tex("\\[ x = ni"), Markup::Math);
+    assert_eq!(tex("\\( x \\) ni"), Markup::Text);
+    assert_eq!(tex("\\begin{align*}\nx &= ni"), Markup::Math);
+    assert_eq!(tex("\\begin{align}x\\end{align} ni"), Markup::Text);
+    assert_eq!(tex("\\begin{verbatim}\nni"), Markup::Code);
+    assert_eq!(tex("\\verb|ni"), Markup::Code);
+    assert_eq!(tex("\\begin{itemize}\n\\item ni"), Markup::Text);
+    assert_eq!(tex("% $ ni"), Markup::Text);
+    assert_eq!(tex("% $\n ni"), Markup::Text);
+    assert!(markup_context("rust", "").is_none());
+}
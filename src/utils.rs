@@ -1,6 +1,7 @@
 use ropey::Rope;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use tower_lsp::lsp_types::{Position, PositionEncodingKind};
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, Url};
 
 use crate::consts::AUTO_TRIGGER_RE;
 
@@ -135,11 +136,114 @@ pub fn rime_default_shared_data_dir() -> &'static str {
     }
 }
 
+/// path string with `/` as separator
+pub fn path_to_slash(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// file path of uri with `/` as separator, or path part of uri if it is not a file
+pub fn uri_to_path(uri: &Url) -> String {
+    match uri.to_file_path() {
+        Ok(path) => path_to_slash(&path),
+        Err(_) => uri.path().to_string(),
+    }
+}
+
+/// if a changed file in user data dir needs deploying, files written by rime itself are ignored
+pub fn need_deploy(user_data_dir: &Path, path: &Path) -> bool {
+    let path = match path.strip_prefix(user_data_dir) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let is_yaml = path.extension().is_some_and(|ext| ext == "yaml");
+    let by_rime = path.starts_with("build")
+        || path.starts_with("sync")
+        || path == Path::new("user.yaml")
+        || path == Path::new("installation.yaml");
+    is_yaml && !by_rime
+}
+
+/// if a string should be treated as glob pattern instead of plain name
+#[inline]
+pub fn is_glob(s: &str) -> bool {
+    s.contains(['*', '?', '/'])
+}
+
+/// match path with a simple glob pattern, `?` and `*` do not match `/` but `**` does,
+/// positions of path reachable by pattern are tracked token by token, so it never backtracks
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let path: Vec<char> = path.chars().collect();
+    let mut reach = vec![false; path.len() + 1];
+    reach[0] = true;
+    // positions reached by any chars, `/` included if `cross` is set
+    let star = |reach: &[bool], cross: bool| {
+        let mut next = reach.to_vec();
+        for j in 1..next.len() {
+            next[j] |= next[j - 1] && (cross || path[j - 1] != '/');
+        }
+        next
+    };
+    let one = |reach: &[bool], matches: &dyn Fn(char) -> bool| {
+        let mut next = vec![false; reach.len()];
+        for j in 1..next.len() {
+            next[j] = reach[j - 1] && matches(path[j - 1]);
+        }
+        next
+    };
+    let mut pattern = pattern.chars().peekable();
+    while let Some(c) = pattern.next() {
+        reach = match c {
+            '*' if pattern.peek() == Some(&'*') => {
+                pattern.next();
+                let any = star(&reach, true);
+                if pattern.peek() == Some(&'/') {
+                    // `**/` matches zero or more directories
+                    pattern.next();
+                    let dirs = one(&any, &|c| c == '/');
+                    reach.iter().zip(dirs).map(|(a, b)| *a || b).collect()
+                } else {
+                    any
+                }
+            }
+            '*' => star(&reach, false),
+            '?' => one(&reach, &|c| c != '/'),
+            c => one(&reach, &|p| p == c),
+        };
+        if !reach.contains(&true) {
+            return false;
+        }
+    }
+    reach[path.len()]
+}
+
 #[inline]
 fn char_is_word(ch: char) -> bool {
     ch.is_alphanumeric() || ch == '_'
 }
 
+/// if a char is a Chinese character, extensions of CJK unified ideographs included
+pub fn is_han(ch: char) -> bool {
+    matches!(ch as u32, 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0x20000..=0x323AF)
+}
+
+/// range of Chinese characters at index, or just before index like a cursor
+pub fn han_range(chars: &[char], index: usize) -> Option<Range<usize>> {
+    let hit = |i: usize| chars.get(i).is_some_and(|c| is_han(*c));
+    let index = match index.checked_sub(1) {
+        _ if hit(index) => index,
+        Some(prev) if hit(prev) => prev,
+        _ => return None,
+    };
+    let start = index - (0..index).rev().take_while(|i| hit(*i)).count();
+    let end = index + 1 + (index + 1..chars.len()).take_while(|i| hit(*i)).count();
+    Some(start..end)
+}
+
+/// compare secrets without leaking the position of first mismatch
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
 #[inline]
 pub fn surrounding_word(s: &str) -> &str {
     let end = s.len();
@@ -165,3 +269,53 @@ fn test_surrounding_word() {
     assert_eq!(surrounding_word("汉，字nihao"), "字nihao");
     assert_eq!(surrounding_word("汉。字nihao"), "字nihao");
 }
+
+#[test]
+fn test_constant_time_eq() {
+    assert!(constant_time_eq(b"", b""));
+    assert!(constant_time_eq(b"secret", b"secret"));
+    assert!(!constant_time_eq(b"secret", b"secreT"));
+    assert!(!constant_time_eq(b"secret", b"secret2"));
+}
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("**", "/home/user/a.md"));
+    assert!(glob_match("**/*.md", "/home/user/a.md"));
+    assert!(glob_match("/home/*/a.md", "/home/user/a.md"));
+    assert!(glob_match("/home/**/a.md", "/home/a.md"));
+    assert!(glob_match("/home/user/?.md", "/home/user/a.md"));
+    assert!(!glob_match("/home/*.md", "/home/user/a.md"));
+    assert!(!glob_match("**/*.md", "/home/user/a.txt"));
+    assert!(!glob_match("/home/user/?.md", "/home/user/ab.md"));
+    // many stars do not take exponential time
+    let path = "a".repeat(64) + "b";
+    assert!(!glob_match(&"*a".repeat(32), &path));
+    assert!(!glob_match(&"**a".repeat(32), &path));
+    assert!(glob_match("**a*a?a*b", &path));
+}
+
+#[test]
+fn test_need_deploy() {
+    let dir = Path::new("/rime");
+    assert!(need_deploy(dir, Path::new("/rime/default.custom.yaml")));
+    assert!(need_deploy(dir, Path::new("/rime/cn_dicts/8105.dict.yaml")));
+    assert!(!need_deploy(dir, Path::new("/rime/build/default.yaml")));
+    assert!(!need_deploy(dir, Path::new("/rime/user.yaml")));
+    assert!(!need_deploy(dir, Path::new("/rime/installation.yaml")));
+    assert!(!need_deploy(dir, Path::new("/rime/rime.lua")));
+    assert!(!need_deploy(dir, Path::new("/other/default.custom.yaml")));
+}
+
+#[test]
+fn test_han_range() {
+    let chars: Vec<char> = "ab 汉字𠀀, 你".chars().collect();
+    assert_eq!(han_range(&chars, 0), None);
+    assert_eq!(han_range(&chars, 3), Some(3..6));
+    assert_eq!(han_range(&chars, 4), Some(3..6));
+    // cursor after the last character
+    assert_eq!(han_range(&chars, 6), Some(3..6));
+    assert_eq!(han_range(&chars, 7), None);
+    assert_eq!(han_range(&chars, 9), Some(8..9));
+    assert_eq!(han_range(&chars, 10), None);
+}
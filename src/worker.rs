@@ -0,0 +1,43 @@
+use once_cell::sync::Lazy;
+use std::panic::{self, AssertUnwindSafe};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::rime::RimeError;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// the only thread that talks with librime
+static WORKER: Lazy<mpsc::UnboundedSender<Job>> = Lazy::new(|| {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Job>();
+    std::thread::Builder::new()
+        .name("rime-worker".to_string())
+        .spawn(move || {
+            while let Some(job) = rx.blocking_recv() {
+                // a panicking job should not take down all following jobs
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+        })
+        .expect("failed to spawn rime worker thread");
+    tx
+});
+
+/// queue a job on the rime worker thread without waiting for it
+pub fn spawn<F>(f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    let _ = WORKER.send(Box::new(f));
+}
+
+/// run a job on the rime worker thread and wait for its result
+pub async fn call<F, R>(f: F) -> Result<R, RimeError>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    spawn(move || {
+        let _ = tx.send(f());
+    });
+    rx.await.map_err(|_| RimeError::WorkerUnavailable)
+}